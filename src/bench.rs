@@ -0,0 +1,181 @@
+use crate::chunk::estimate_tokens;
+use crate::core::{open_stream, RequestExtra, Sampling};
+use crate::retry::RetryPolicy;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use openai::{
+    chat::{ChatCompletionDelta, ChatCompletionMessage},
+    Credentials,
+};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BenchFormat {
+    /// Percentiles per measurement
+    Text,
+    /// One line per run
+    Csv,
+    /// Every run and the percentiles
+    Json,
+}
+
+/// Timing of one benchmark request.
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub first_token: Duration,
+    pub total: Duration,
+    /// Estimated from the answer text, since streamed responses carry no usage.
+    pub tokens: usize,
+}
+
+impl Run {
+    /// Generation speed after the first token arrived.
+    pub fn tokens_per_sec(&self) -> f64 {
+        let generating = (self.total - self.first_token).as_secs_f64();
+        if generating > 0.0 {
+            self.tokens as f64 / generating
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Sends one request and measures it without printing the answer.
+pub async fn run(
+    messages: Vec<ChatCompletionMessage>,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<Run> {
+    let started = Instant::now();
+    let mut stream = open_stream(
+        ChatCompletionDelta::builder(model, messages),
+        credentials,
+        extra,
+        sampling,
+        policy,
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
+    let mut first_token = None;
+    let mut answer = String::new();
+    while let Some(delta) = stream.recv().await {
+        let delta = delta?;
+        let content = delta
+            .choices
+            .iter()
+            .find(|choice| choice.index == 0)
+            .and_then(|choice| choice.delta.content.as_deref())
+            .unwrap_or_default();
+        if !content.is_empty() && first_token.is_none() {
+            first_token = Some(started.elapsed());
+        }
+        answer.push_str(content);
+    }
+    let total = started.elapsed();
+    Ok(Run {
+        first_token: first_token.unwrap_or(total),
+        total,
+        tokens: estimate_tokens(&answer),
+    })
+}
+
+/// The value below which `p` percent of `values` fall (nearest rank).
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    match sorted.len() {
+        0 => 0.0,
+        n => sorted[((p / 100.0 * n as f64).ceil() as usize).clamp(1, n) - 1],
+    }
+}
+
+fn measurements(runs: &[Run]) -> [(&'static str, Vec<f64>); 3] {
+    [
+        (
+            "first_token_ms",
+            runs.iter()
+                .map(|run| run.first_token.as_secs_f64() * 1000.0)
+                .collect(),
+        ),
+        (
+            "total_ms",
+            runs.iter()
+                .map(|run| run.total.as_secs_f64() * 1000.0)
+                .collect(),
+        ),
+        (
+            "tokens_per_sec",
+            runs.iter().map(Run::tokens_per_sec).collect(),
+        ),
+    ]
+}
+
+/// A table of p50/p90/p99/mean for time to first token, total latency and tokens/sec.
+pub fn text(runs: &[Run], failed: usize) -> String {
+    let mut lines = vec![
+        format!("runs: {} ({} failed)", runs.len() + failed, failed),
+        format!(
+            "{:<16}{:>10}{:>10}{:>10}{:>10}",
+            "", "p50", "p90", "p99", "mean"
+        ),
+    ];
+    for (name, values) in measurements(runs) {
+        let mean = values.iter().sum::<f64>() / values.len().max(1) as f64;
+        lines.push(format!(
+            "{:<16}{:>10.1}{:>10.1}{:>10.1}{:>10.1}",
+            name,
+            percentile(&values, 50.0),
+            percentile(&values, 90.0),
+            percentile(&values, 99.0),
+            mean
+        ));
+    }
+    lines.join("\n")
+}
+
+pub fn csv(runs: &[Run]) -> String {
+    let mut lines = vec![String::from(
+        "run,first_token_ms,total_ms,tokens,tokens_per_sec",
+    )];
+    for (i, run) in runs.iter().enumerate() {
+        lines.push(format!(
+            "{},{},{},{},{:.1}",
+            i + 1,
+            run.first_token.as_millis(),
+            run.total.as_millis(),
+            run.tokens,
+            run.tokens_per_sec()
+        ));
+    }
+    lines.join("\n")
+}
+
+pub fn json(runs: &[Run], failed: usize) -> Value {
+    let summary = measurements(runs)
+        .into_iter()
+        .map(|(name, values)| {
+            (
+                name.to_string(),
+                json!({
+                    "p50": percentile(&values, 50.0),
+                    "p90": percentile(&values, 90.0),
+                    "p99": percentile(&values, 99.0),
+                }),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    json!({
+        "runs": runs.iter().map(|run| json!({
+            "first_token_ms": run.first_token.as_millis() as u64,
+            "total_ms": run.total.as_millis() as u64,
+            "tokens": run.tokens,
+            "tokens_per_sec": run.tokens_per_sec(),
+        })).collect::<Vec<_>>(),
+        "failed": failed,
+        "summary": summary,
+    })
+}
@@ -1,109 +1,679 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use openai::{
-    chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole},
+    chat::{
+        ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole,
+        ChatCompletionResponseFormat,
+    },
     Credentials,
 };
-use std::env;
-use std::fs::File;
-use std::io::{self, IsTerminal, Read};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use FerriteChatter::{
-    config::Config,
-    core::{ask, Model, DEFAULT_MODEL},
+    bench::{self, BenchFormat},
+    bookmarks,
+    cli::{bootstrap, CommonArgs},
+    core::{
+        collect, is_empty, numbered_choices, open_stream, parse_key_value, refusal, Model,
+        ReasoningEffort, RequestExtra, Sampling,
+    },
+    document,
+    export::{self, ExportFormat},
+    fence::fence,
+    follow, metrics,
+    prompt::{self, StdinPosition},
+    render::{print_answer, Printer},
+    retry::RetryPolicy,
+    review::{self, Finding, Findings, ReviewFormat, REVIEW_PROMPT},
+    templates::{self, Template},
+    workflow::Workflow,
 };
 
+/// Token budget of a single review request.
+const REVIEW_BATCH_TOKENS: usize = 6000;
+
+/// Files larger than this are sent as several labelled messages.
+const FILE_PART_TOKENS: usize = 16000;
+
+/// Token budget of the files referenced with `@path` in the prompt.
+const REFERENCE_TOKENS: usize = 32000;
+
+/// Asks the `--judge` model to rank the answers of `--models`.
+const JUDGE_PROMPT: &str = r#"
+You compare answers of several language models to the same question.
+Rank them from best to worst by correctness, completeness and clarity, and explain each placement in one or two sentences.
+Refer to the answers by their labels.
+"#;
+
+/// Token budget of a `--follow` batch; longer bursts are split.
+const FOLLOW_BATCH_TOKENS: usize = 8000;
+
+/// Exit status of `--fail-on-empty`.
+const EMPTY_EXIT: i32 = 3;
+
+/// Exit status of `--fail-on-refusal`.
+const REFUSAL_EXIT: i32 = 4;
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(flatten)]
+    common: CommonArgs,
     /// Open Prompt(General Prompt)
     #[clap(long = "general", short = 'g')]
     general: Option<String>,
-    /// OpenAI API Key
-    #[clap(long = "key", short = 'k')]
-    key: Option<String>,
-    /// OpenAI API Base URL
-    #[clap(long = "base-url", short = 'b')]
-    base_url: Option<String>,
-    /// OpenAI Model
-    #[clap(long = "model", short = 'm', value_enum, default_value = "gpt-4o")]
-    model: Option<Model>,
+    /// Start from a template in the templates directory of the config
+    #[clap(long = "template", short = 't', conflicts_with_all = ["general", "review"])]
+    template: Option<String>,
+    /// Template or workflow variable (name=value, `@path` reads a file; repeatable)
+    #[clap(long = "var", value_parser = parse_key_value)]
+    vars: Vec<(String, String)>,
+    /// End-user identifier sent as the `user` request field
+    #[clap(long = "user")]
+    user: Option<String>,
+    /// Request metadata (key=value, repeatable)
+    #[clap(long = "metadata", value_parser = parse_key_value)]
+    metadata: Vec<(String, String)>,
+    /// Ask the provider not to store the conversation or train on it
+    #[clap(long = "no-train")]
+    no_train: bool,
+    /// Sampling temperature
+    #[clap(long = "temperature")]
+    temperature: Option<f32>,
+    /// Nucleus sampling probability mass
+    #[clap(long = "top-p")]
+    top_p: Option<f32>,
+    /// Maximum number of tokens in the answer
+    #[clap(long = "max-tokens")]
+    max_tokens: Option<u64>,
+    /// Reasoning effort for reasoning models (o1, o3, ...)
+    #[clap(long = "reasoning-effort", value_enum)]
+    reasoning_effort: Option<ReasoningEffort>,
+    /// Number of answers to generate; more than one prints them as a numbered list
+    #[clap(long = "choices")]
+    choices: Option<u8>,
+    /// Ask several models at once (comma separated) and print their answers one after another
+    #[clap(
+        long = "models",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with_all = ["model", "choices", "review"]
+    )]
+    models: Vec<Model>,
+    /// Model that ranks the answers of `--models`
+    #[clap(long = "judge", value_enum, requires = "models")]
+    judge: Option<Model>,
+    /// Context file (plain text, PDF, DOCX or HTML)
     #[clap(long = "file", short = 'f')]
     file: Option<String>,
+    /// Web page whose text is added as context
+    #[clap(long = "url")]
+    url: Option<String>,
+    /// Review a unified diff from stdin (or the `--file` diff or source) and print findings
+    #[clap(long = "review")]
+    review: bool,
+    /// Output format of `--review`
+    #[clap(
+        long = "review-format",
+        value_enum,
+        default_value = "text",
+        requires = "review"
+    )]
+    review_format: ReviewFormat,
+    /// Also write the prompt and the answer to a file
+    #[clap(
+        long = "output",
+        short = 'o',
+        conflicts_with_all = ["review", "models", "bench", "workflow"]
+    )]
+    output: Option<PathBuf>,
+    /// Format of `--output` (default: from the file extension)
+    #[clap(long = "format", value_enum, requires = "output")]
+    format: Option<ExportFormat>,
+    /// Don't print the answer, only write it to `--output`
+    #[clap(long = "quiet", short = 'q', requires = "output")]
+    quiet: bool,
+    /// Send piped input larger than `large_input_bytes` without asking
+    #[clap(long = "force-large-input")]
+    force_large_input: bool,
+    /// Exit with status 3 when the answer is empty
+    #[clap(long = "fail-on-empty")]
+    fail_on_empty: bool,
+    /// Exit with status 4 when the model refused or the content filter stopped the answer
+    #[clap(long = "fail-on-refusal")]
+    fail_on_refusal: bool,
+    /// Where piped stdin goes relative to the prompt argument
+    #[clap(long = "stdin-position", value_enum, default_value = "before")]
+    stdin_position: StdinPosition,
+    /// Send the prompt RUNS times and print latency statistics instead of the answer
+    #[clap(
+        long = "bench",
+        value_name = "RUNS",
+        conflicts_with_all = ["review", "models", "choices"]
+    )]
+    bench: Option<usize>,
+    /// Output format of `--bench`
+    #[clap(
+        long = "bench-format",
+        value_enum,
+        default_value = "text",
+        requires = "bench"
+    )]
+    bench_format: BenchFormat,
+    /// Keep reading stdin and analyse it in batches as lines arrive (e.g. `tail -f`)
+    #[clap(
+        long = "follow",
+        conflicts_with_all = ["review", "models", "bench", "workflow", "choices", "output"]
+    )]
+    follow: bool,
+    /// Seconds to gather lines after the first one of a `--follow` batch
+    #[clap(
+        long = "window",
+        value_name = "SECS",
+        default_value_t = 10,
+        requires = "follow"
+    )]
+    window: u64,
+    /// Run the steps of a workflow file, each fed with the outputs before it
+    #[clap(
+        long = "workflow",
+        conflicts_with_all = ["template", "review", "models", "bench", "choices", "file", "url"]
+    )]
+    workflow: Option<PathBuf>,
+    /// Read the prompt from a file
+    #[clap(long = "prompt-file", conflicts_with = "prompt")]
+    prompt_file: Option<PathBuf>,
     /// Prompt
     prompt: Option<String>,
 }
 
+/// Sends every batch for review and gathers the findings.
+async fn review(
+    batches: Vec<String>,
+    prompt: &str,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    for batch in batches {
+        let messages = vec![
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some(prompt.to_string()),
+                ..Default::default()
+            },
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(batch),
+                ..Default::default()
+            },
+        ];
+        let stream = open_stream(
+            ChatCompletionDelta::builder(model, messages)
+                .response_format(ChatCompletionResponseFormat::json_object()),
+            credentials,
+            extra,
+            sampling,
+            policy,
+        )
+        .await
+        .with_context(|| "Can't open Stream")?;
+        let answer = collect(stream)
+            .await?
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .with_context(|| "Can't get choices")?;
+        let batch: Findings =
+            serde_json::from_str(&answer).with_context(|| "Can't parse the review")?;
+        findings.extend(batch.findings);
+    }
+    Ok(findings)
+}
+
+/// One answer of `--models`, collected without printing.
+async fn answer(
+    messages: Vec<ChatCompletionMessage>,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<String> {
+    let stream = open_stream(
+        ChatCompletionDelta::builder(model, messages),
+        credentials,
+        extra,
+        sampling,
+        policy,
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
+    collect(stream)
+        .await?
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .with_context(|| "Can't get choices")
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let config = Config::load()?;
-    let mut stdin = io::stdin();
-    let prompt = if !stdin.is_terminal() {
-        let mut s = String::new();
-        let _ = stdin.read_to_string(&mut s);
-        Some(format!(
-            "{}\n{}",
-            s,
-            args.prompt.unwrap_or(String::default())
-        ))
-    } else {
-        args.prompt
-    }
-    .with_context(|| "Please provide input via a pipe or pass the prompt as an argument.")?;
-
-    let key = args.key.unwrap_or(
-        config.get_openai_api_key().clone().unwrap_or(
-            env::var("OPENAI_API_KEY")
-                .with_context(|| "You need to set API key to the `OPENAI_API_KEY`")?,
-        ),
-    );
-    let base_url = args
-        .base_url
-        .unwrap_or(config.get_openai_base_url().clone().unwrap_or(
-            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string()),
-        ));
-    let credentials = Credentials::new(key, base_url);
+    let (credentials, default_model, config, transport) = bootstrap(&args.common, "fask")?;
+    // `--follow` reads stdin as it goes instead of up front.
+    let piped = match args.follow {
+        true => None,
+        false => prompt::read_stdin(args.stdin_position),
+    };
+
+    let mut metadata = config.get_metadata().clone().unwrap_or_default();
+    metadata.extend(args.metadata);
+    let extra = RequestExtra::default()
+        .transport(transport.clone())
+        .user(args.user.or(config.get_user().clone()))
+        .metadata(metadata)
+        .headers(config.get_headers().clone().unwrap_or_default())
+        .no_train(
+            args.no_train || config.no_train_enabled(),
+            credentials.base_url(),
+        );
 
+    let template = args.template.as_deref().map(templates::load).transpose()?;
     let model = args
+        .common
         .model
-        .unwrap_or(config.get_default_model().clone().unwrap_or(DEFAULT_MODEL))
+        .clone()
+        .or(template
+            .as_ref()
+            .and_then(|template| template.model.clone()))
+        .unwrap_or(default_model.clone())
         .as_str();
 
-    let role = if !model.starts_with("o1") {
-        ChatCompletionMessageRole::System
-    } else {
-        ChatCompletionMessageRole::User
+    let requested = Sampling {
+        temperature: args.temperature,
+        top_p: args.top_p,
+        max_tokens: args.max_tokens,
+        reasoning_effort: args.reasoning_effort,
+        n: args.choices,
+    }
+    .or(template
+        .as_ref()
+        .map(Template::sampling)
+        .unwrap_or_default());
+    let sampling = requested.clone().or(config.sampling(model));
+
+    if let Some(input) = &piped {
+        prompt::confirm_large_input(
+            input,
+            config.large_input_limit(),
+            args.force_large_input,
+            config.input_price(model),
+        )?;
+    }
+
+    if args.review {
+        let (input, path) = match (&args.file, piped) {
+            (Some(path), _) => (
+                read_to_string(path).with_context(|| format!("Can't read {}", path))?,
+                path.clone(),
+            ),
+            (None, Some(input)) => (input, String::from("stdin")),
+            (None, None) => {
+                bail!("Please provide a diff via a pipe or pass a file with --file.")
+            }
+        };
+        // A prompt argument adds instructions to the review, e.g. "focus on error handling".
+        let prompt = match args.prompt {
+            Some(instructions) => format!("{}\n{}", REVIEW_PROMPT, instructions),
+            None => String::from(REVIEW_PROMPT),
+        };
+        let started = Instant::now();
+        let findings = review(
+            review::batches(&input, &path, REVIEW_BATCH_TOKENS),
+            &prompt,
+            model,
+            &credentials,
+            &extra,
+            &sampling,
+            &config.retry_policy(),
+        )
+        .await?;
+        metrics::record_request(&config, model, started.elapsed());
+        match args.review_format {
+            ReviewFormat::Text => println!("{}", review::text(&findings)),
+            ReviewFormat::Sarif => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&review::sarif(&findings))?
+                )
+            }
+        }
+        return Ok(());
+    }
+
+    let piped = piped.map(|s| {
+        fence(&s)
+            .filter(|_| config.fence_code_enabled())
+            .unwrap_or(s)
+    });
+    let argument = match args.prompt_file {
+        Some(path) => {
+            Some(read_to_string(&path).with_context(|| format!("Can't read {}", path.display()))?)
+        }
+        None => args.prompt,
+    };
+    let argument = argument
+        .map(|argument| prompt::expand_references(&argument, REFERENCE_TOKENS))
+        .transpose()?;
+    // Templates and workflows can be used on their own; otherwise there has to be a prompt.
+    let mut prompt = match (&piped, &argument) {
+        (None, None) if template.is_some() || args.workflow.is_some() || args.follow => None,
+        _ => Some(prompt::compose(piped, argument, args.stdin_position)?),
     };
 
+    if let Some(path) = &args.workflow {
+        let workflow = Workflow::load(path)?;
+        let mut values = HashMap::new();
+        for (name, value) in &args.vars {
+            values.insert(name.clone(), templates::value(value)?);
+        }
+        if let Some(prompt) = prompt {
+            values.insert(String::from("input"), prompt);
+        }
+        let mut printer = Printer::new();
+        let output = workflow
+            .run(
+                values,
+                args.common
+                    .model
+                    .clone()
+                    .or(workflow.model.clone())
+                    .unwrap_or(default_model),
+                &credentials,
+                &extra,
+                |model| requested.clone().or(config.sampling(model)),
+                &config.retry_policy(),
+                |text| printer.print(text),
+            )
+            .await?;
+        printer.finish()?;
+        if args.fail_on_empty && output.trim().is_empty() {
+            eprintln!("The answer is empty");
+            exit(EMPTY_EXIT)
+        }
+        return Ok(());
+    }
+
     let mut messages = Vec::new();
-    if let Some(general) = args.general {
+    let seeded = match &template {
+        Some(template) => {
+            let mut values = HashMap::new();
+            for (name, value) in &args.vars {
+                values.insert(name.clone(), templates::value(value)?);
+            }
+            // `{{input}}` takes the prompt instead of a separate message.
+            if template.variables().iter().any(|name| name == "input") {
+                if let Some(prompt) = prompt.take() {
+                    values.insert(String::from("input"), prompt);
+                }
+            }
+            template.render(&values)?
+        }
+        None => Vec::new(),
+    };
+    let has_system = seeded
+        .iter()
+        .any(|message| message.role == ChatCompletionMessageRole::System);
+    if let Some(general) = args
+        .general
+        .or(config.get_system_prompt().clone())
+        .filter(|_| !has_system)
+    {
         messages.push(ChatCompletionMessage {
-            role,
+            role: ChatCompletionMessageRole::System,
             content: Some(general),
             ..Default::default()
         })
     }
+    messages.extend(seeded);
     if let Some(path) = args.file {
-        let mut input = String::new();
-        let _ = File::open(path)?.read_to_string(&mut input);
+        let input = document::read_text(Path::new(&path))?;
+        for part in document::parts(&path, &input, FILE_PART_TOKENS) {
+            messages.push(ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(part),
+                ..Default::default()
+            })
+        }
+    }
+
+    if let Some(url) = args.url {
         messages.push(ChatCompletionMessage {
             role: ChatCompletionMessageRole::User,
-            content: Some(input),
+            content: Some(document::fetch(transport.as_ref(), &url).await?),
             ..Default::default()
         })
     }
 
-    messages.push(ChatCompletionMessage {
-        role: ChatCompletionMessageRole::User,
-        content: Some(prompt),
-        ..Default::default()
-    });
+    if let Some(prompt) = prompt {
+        messages.push(ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(prompt),
+            ..Default::default()
+        });
+    }
 
-    let stream = ChatCompletionDelta::builder(model, messages.clone())
-        .credentials(credentials.clone())
-        .create_stream()
+    if args.follow {
+        let policy = config.retry_policy();
+        let mut lines = follow::lines();
+        while let Some(batch) = follow::next_batch(
+            &mut lines,
+            Duration::from_secs(args.window),
+            FOLLOW_BATCH_TOKENS,
+        )
         .await
-        .with_context(|| "Can't open Stream")?;
+        {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            println!("=== {} ({} lines) ===", bookmarks::date(now), batch.len());
+            let mut messages = messages.clone();
+            messages.push(ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(batch.join("\n")),
+                ..Default::default()
+            });
+            let started = Instant::now();
+            // A failed batch is reported and the next one is analysed anyway.
+            let result = match open_stream(
+                ChatCompletionDelta::builder(model, messages),
+                &credentials,
+                &extra,
+                &sampling,
+                &policy,
+            )
+            .await
+            {
+                Ok(stream) => print_answer(stream).await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => metrics::record_request(&config, model, started.elapsed()),
+                Err(e) => eprintln!("{:#}", e),
+            }
+            println!();
+        }
+        return Ok(());
+    }
+
+    if let Some(count) = args.bench {
+        let policy = config.retry_policy();
+        let mut runs = Vec::new();
+        let mut failed = 0;
+        for i in 1..=count {
+            match bench::run(
+                messages.clone(),
+                model,
+                &credentials,
+                &extra,
+                &sampling,
+                &policy,
+            )
+            .await
+            {
+                Ok(run) => {
+                    metrics::record_request(&config, model, run.total);
+                    eprintln!("run {}/{}: {} ms", i, count, run.total.as_millis());
+                    runs.push(run);
+                }
+                Err(e) => {
+                    eprintln!("run {}/{}: {:#}", i, count, e);
+                    failed += 1;
+                }
+            }
+        }
+        match args.bench_format {
+            BenchFormat::Text => println!("{}", bench::text(&runs, failed)),
+            BenchFormat::Csv => println!("{}", bench::csv(&runs)),
+            BenchFormat::Json => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&bench::json(&runs, failed))?
+                )
+            }
+        }
+        return Ok(());
+    }
+
+    if !args.models.is_empty() {
+        let question = messages
+            .last()
+            .and_then(|message| message.content.clone())
+            .unwrap_or_default();
+        let policy = config.retry_policy();
+        let mut pending = args
+            .models
+            .iter()
+            .map(|model| {
+                let model = model.as_str();
+                let sampling = requested.clone().or(config.sampling(model));
+                let messages = messages.clone();
+                let (credentials, extra, policy) = (&credentials, &extra, &policy);
+                async move {
+                    let started = Instant::now();
+                    let answer =
+                        answer(messages, model, credentials, extra, &sampling, policy).await;
+                    (model, started.elapsed(), answer)
+                }
+            })
+            .collect::<FuturesUnordered<_>>();
 
-    ask(stream).await.map(|_| ())
+        // Answers are printed as they arrive, so the fastest model shows up first.
+        let mut answers = Vec::new();
+        while let Some((model, elapsed, answer)) = pending.next().await {
+            println!("=== {} ({:.1}s) ===", model, elapsed.as_secs_f64());
+            match answer {
+                Ok(answer) => {
+                    metrics::record_request(&config, model, elapsed);
+                    println!("{}\n", answer.trim());
+                    answers.push((model, answer));
+                }
+                Err(e) => println!("{:#}\n", e),
+            }
+        }
+
+        if let Some(judge) = args.judge {
+            let judge = judge.as_str();
+            let answers = answers
+                .iter()
+                .map(|(model, answer)| format!("[{}]\n{}", model, answer.trim()))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            println!("=== judge: {} ===", judge);
+            let started = Instant::now();
+            let stream = open_stream(
+                ChatCompletionDelta::builder(
+                    judge,
+                    vec![
+                        ChatCompletionMessage {
+                            role: ChatCompletionMessageRole::System,
+                            content: Some(JUDGE_PROMPT.to_string()),
+                            ..Default::default()
+                        },
+                        ChatCompletionMessage {
+                            role: ChatCompletionMessageRole::User,
+                            content: Some(format!(
+                                "Question:\n{}\n\nAnswers:\n{}",
+                                question, answers
+                            )),
+                            ..Default::default()
+                        },
+                    ],
+                ),
+                &credentials,
+                &extra,
+                &requested.clone().or(config.sampling(judge)),
+                &policy,
+            )
+            .await
+            .with_context(|| "Can't open Stream")?;
+            print_answer(stream).await?;
+            metrics::record_request(&config, judge, started.elapsed());
+        }
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let stream = open_stream(
+        ChatCompletionDelta::builder(model, messages.clone()),
+        &credentials,
+        &extra,
+        &sampling,
+        &config.retry_policy(),
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
+
+    let completion = if args.quiet {
+        collect(stream).await?
+    } else if sampling.multiple() {
+        let completion = collect(stream).await?;
+        println!("{}", numbered_choices(&completion));
+        completion
+    } else {
+        print_answer(stream).await?
+    };
+    metrics::record_request(&config, model, started.elapsed());
+
+    if let Some(path) = &args.output {
+        let prompt = messages
+            .iter()
+            .rev()
+            .find(|message| message.role == ChatCompletionMessageRole::User)
+            .and_then(|message| message.content.as_deref())
+            .unwrap_or_default();
+        let format = args.format.unwrap_or(ExportFormat::from_path(path));
+        export::save(path, prompt, &completion, format)?;
+    }
+
+    if args.fail_on_refusal {
+        if let Some(reason) = refusal(&completion) {
+            eprintln!("The answer was refused ({})", reason);
+            exit(REFUSAL_EXIT)
+        }
+    }
+    if args.fail_on_empty && is_empty(&completion) {
+        eprintln!("The answer is empty");
+        exit(EMPTY_EXIT)
+    }
+    Ok(())
 }
@@ -1,88 +1,429 @@
-use anyhow::{Context, Result};
-use clap::Parser;
-use inquire::{Confirm, Editor, Text};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use inquire::{Confirm, Editor, Select, Text};
 use openai::{
     chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole},
     Credentials,
 };
-use std::env;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::time::Instant;
 use FerriteChatter::{
+    bookmarks::{Bookmark, Bookmarks},
+    cassette,
+    chunk::estimate_tokens,
+    cli::{bootstrap, CommonArgs},
+    commands::{self, CommandKind},
+    completion::Completer,
     config::Config,
-    core::{ask, Model, DEFAULT_MODEL},
+    core::{
+        collect, numbered_choices, open_stream, parse_key_value, Model, ReasoningEffort,
+        RequestExtra, Sampling,
+    },
+    document,
+    history::History,
+    input,
+    journal::{self, Journal},
+    metrics, prompt, prompts,
+    render::print_answer,
+    retry::RetryPolicy,
+    sink, snippets, summary,
+    templates::{self, Template},
+    terminal::{file_link, link, TerminalIntegration},
+    walk,
 };
 
 const SEED_PROMPT: &'static str = r#"
 You are an engineer's assistant.
 The user can reset the current state of the chat by inputting 'reset'.
 The user can activate the editor by entering 'v', allowing them to input multiple lines of prompts.
+The user can drop the last exchange with '/undo' or rewrite their last message with '/edit'.
+The user can regenerate the last answer with '/retry', optionally with another model ('/retry -m <model>').
+The user can switch the model for the rest of the conversation with '/model <model>'.
+The user can save the last answer with '/bookmark [note]'.
+The user can insert a saved snippet into the prompt with '/snippet <name>'.
+The user can search the imported prompt catalogs and use a prompt from them with '/prompts [query]'.
+The user can attach a file by mentioning it as '@path/to/file'.
+The user can switch the API endpoint with '/endpoint <url>' and go back with '/endpoint reset'.
+The user can toggle multi-line compose mode, where Enter adds a line, with '/compose'.
+The user can add the text of a web page to the conversation with '/fetch <url>'.
+The user can change sampling parameters with '/set temperature 0.2' (also top_p, max_tokens, reasoning_effort and n).
+The user can get a summary of the conversation so far with '/summarize'.
+The user can list the commands with '/help'.
 To terminate, the user needs to input "exit".
 "#;
 
+/// Files larger than this are sent as several labelled messages.
+const FILE_PART_TOKENS: usize = 16000;
+
+/// Token budget of the files referenced with `@path` in a message.
+const REFERENCE_TOKENS: usize = 32000;
+
+/// Token budget of the files loaded with `--dir` unless `--dir-tokens` is given.
+const DIR_TOKENS: usize = 64000;
+
+/// Exchanges left as they are when `compact_tokens` folds the conversation into a summary.
+const COMPACT_KEEP: usize = 2;
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(flatten)]
+    common: CommonArgs,
     /// Open Prompt(General Prompt)
-    #[clap(long = "general", short = 'g')]
+    #[clap(long = "general", short = 'g', conflicts_with = "preset")]
     general: Option<String>,
-    /// OpenAI API Key
-    #[clap(long = "key", short = 'k')]
-    key: Option<String>,
-    /// OpenAI API Base URL
-    #[clap(long = "base-url", short = 'b')]
-    base_url: Option<String>,
-    /// OpenAI Model
-    #[clap(long = "model", short = 'm', value_enum)]
-    model: Option<Model>,
-    /// Initial context file
+    /// Start from a template in the templates directory of the config
+    #[clap(long = "template", short = 't', conflicts_with_all = ["general", "preset"])]
+    template: Option<String>,
+    /// Template variable (name=value, `@path` reads a file; repeatable)
+    #[clap(long = "var", value_parser = parse_key_value, requires = "template")]
+    vars: Vec<(String, String)>,
+    /// Named prompt preset defined in the config
+    #[clap(long = "preset", short = 'p')]
+    preset: Option<String>,
+    /// End-user identifier sent as the `user` request field
+    #[clap(long = "user")]
+    user: Option<String>,
+    /// Request metadata (key=value, repeatable)
+    #[clap(long = "metadata", value_parser = parse_key_value)]
+    metadata: Vec<(String, String)>,
+    /// Ask the provider not to store the conversation or train on it
+    #[clap(long = "no-train")]
+    no_train: bool,
+    /// Sampling temperature
+    #[clap(long = "temperature")]
+    temperature: Option<f32>,
+    /// Nucleus sampling probability mass
+    #[clap(long = "top-p")]
+    top_p: Option<f32>,
+    /// Maximum number of tokens in the answer
+    #[clap(long = "max-tokens")]
+    max_tokens: Option<u64>,
+    /// Reasoning effort for reasoning models (o1, o3, ...)
+    #[clap(long = "reasoning-effort", value_enum)]
+    reasoning_effort: Option<ReasoningEffort>,
+    /// Number of answers to generate; with more than one you pick which one to keep
+    #[clap(long = "choices")]
+    choices: Option<u8>,
+    /// Initial context file (plain text, PDF, DOCX or HTML)
     #[clap(long = "file", short = 'f')]
     file: Option<String>,
+    /// Start in compose mode: Enter adds a line, Ctrl-D or Alt+Enter sends
+    #[clap(long = "compose")]
+    compose: bool,
+    /// Directory whose files are loaded as initial context (respects .gitignore)
+    #[clap(long = "dir")]
+    dir: Option<String>,
+    /// Only load files of `--dir` matching this glob (repeatable, e.g. '*.rs')
+    #[clap(long = "include", requires = "dir")]
+    include: Vec<String>,
+    /// Skip files of `--dir` matching this glob (repeatable, e.g. 'target/**')
+    #[clap(long = "exclude", requires = "dir")]
+    exclude: Vec<String>,
+    /// Token budget of `--dir`; files beyond it are only listed
+    #[clap(long = "dir-tokens", default_value_t = DIR_TOKENS, requires = "dir")]
+    dir_tokens: usize,
+}
+
+/// Index of the last message the user typed, never reaching into the initial context.
+fn last_user_message(messages: &[ChatCompletionMessage], boundary: usize) -> Option<usize> {
+    messages
+        .get(boundary..)?
+        .iter()
+        .rposition(|m| m.role == ChatCompletionMessageRole::User)
+        .map(|index| boundary + index)
+}
+
+/// Ends the journal, telling where the messages replaced by summaries were kept.
+fn finish(journal: Journal) -> Result<()> {
+    if let Some(kept) = journal.finish()? {
+        println!(
+            "The messages replaced by summaries are kept in {}",
+            file_link(&kept)
+        );
+    }
+    Ok(())
+}
+
+fn title(model: &str, persona: Option<&str>) -> String {
+    match persona {
+        Some(persona) => format!("fchat - {} ({})", model, persona),
+        None => format!("fchat - {}", model),
+    }
+}
+
+/// Streams the answer for the current conversation to stdout and appends it to `messages`.
+/// If the request fails the pending user message is dropped so the session can go on.
+/// The answer is also handed to the persona's output sinks. Returns whether an answer arrived.
+#[allow(clippy::too_many_arguments)]
+async fn send(
+    messages: &mut Vec<ChatCompletionMessage>,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    config: &Config,
+    persona: Option<&str>,
+    journal: &mut Journal,
+) -> Result<bool> {
+    // Journal the question before waiting for the answer so a crash doesn't lose it.
+    journal.sync(messages)?;
+    let started = Instant::now();
+    match complete(
+        messages,
+        model,
+        credentials,
+        extra,
+        sampling,
+        &config.retry_policy(),
+    )
+    .await
+    {
+        Ok(answer) => {
+            metrics::record_request(config, model, started.elapsed());
+            if let Some(content) = &answer.content {
+                sink::deliver(config.outputs(persona), content);
+            }
+            messages.push(answer);
+            Ok(true)
+        }
+        Err(e) => {
+            messages.pop();
+            eprintln!("{:#}", e);
+            Ok(false)
+        }
+    }
+}
+
+async fn complete(
+    messages: &[ChatCompletionMessage],
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<ChatCompletionMessage> {
+    let stream = open_stream(
+        ChatCompletionDelta::builder(model, messages.to_vec()),
+        credentials,
+        extra,
+        sampling,
+        policy,
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
+
+    if !sampling.multiple() {
+        return Ok(print_answer(stream)
+            .await?
+            .choices
+            .first()
+            .with_context(|| "Can't get choices")?
+            .message
+            .clone());
+    }
+
+    let completion = collect(stream).await?;
+    println!("{}", numbered_choices(&completion));
+    let options = (1..=completion.choices.len()).collect::<Vec<_>>();
+    let picked = match &options[..] {
+        [] => bail!("Can't get choices"),
+        [_] => 1,
+        _ => Select::new("Keep answer:", options).prompt()?,
+    };
+    Ok(completion.choices[picked - 1].message.clone())
+}
+
+/// Once the conversation after `boundary` outgrows `budget` tokens, replaces all but the
+/// last `COMPACT_KEEP` exchanges with a summary in `language`. A summary written in another
+/// language (after `summary_language` changed) is summarized again, even within budget.
+/// The journal keeps the replaced messages.
+#[allow(clippy::too_many_arguments)]
+async fn compact(
+    messages: &mut Vec<ChatCompletionMessage>,
+    boundary: usize,
+    budget: Option<usize>,
+    language: &str,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    policy: &RetryPolicy,
+    journal: &mut Journal,
+) -> Result<()> {
+    let over = budget.is_some_and(|budget| summary::tokens(&messages[boundary..]) > budget);
+    let split = over
+        .then(|| summary::split(messages, boundary, COMPACT_KEEP))
+        .flatten()
+        .or_else(|| {
+            summary::stale(&messages[boundary..], language).map(|index| boundary + index + 1)
+        });
+    let Some(split) = split else {
+        return Ok(());
+    };
+    let text = summary::generate_summary(
+        &messages[boundary..split],
+        language,
+        model,
+        credentials,
+        extra,
+        policy,
+    )
+    .await?;
+    journal.compact(&text, &messages[boundary..split])?;
+    let replaced = split - boundary;
+    messages.splice(boundary..split, [summary::message(&text, language)]);
+    journal.sync(messages)?;
+    if over {
+        println!(
+            "Compacted {} earlier messages into a summary (~{} tokens now).",
+            replaced,
+            summary::tokens(&messages[boundary..])
+        );
+    } else {
+        println!("Summarized the earlier conversation again in {}.", language);
+    }
+    Ok(())
+}
+
+/// The input prompt, e.g. `[gpt-4o|compose] > `: the model, then the endpoint when it was
+/// switched with `/endpoint`, compose mode and the number of answers when more than one.
+fn prompt_line(model: &str, endpoint: Option<&str>, compose: bool, sampling: &Sampling) -> String {
+    let mut parts = vec![model.to_string()];
+    if let Some(endpoint) = endpoint {
+        parts.push(host(endpoint).to_string());
+    }
+    if compose {
+        parts.push(String::from("compose"));
+    }
+    if let Some(n) = sampling.n.filter(|_| sampling.multiple()) {
+        parts.push(format!("n={}", n));
+    }
+    format!("[{}] > ", parts.join("|"))
+}
+
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.split('/').next().unwrap_or(rest)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let config = Config::load()?;
+    let (mut credentials, default_model, config, transport) = bootstrap(&args.common, "fchat")?;
+    let initial_endpoint = credentials.base_url().to_string();
+    if config.warmup_enabled() && !cassette::replaying() {
+        transport.warm_up(&credentials);
+    }
+    let no_train = args.no_train || config.no_train_enabled();
 
-    let key = args.key.unwrap_or(
-        config.get_openai_api_key().clone().unwrap_or(
-            env::var("OPENAI_API_KEY")
-                .with_context(|| "You need to set API key to the `OPENAI_API_KEY`")?,
-        ),
-    );
-    let base_url = args
-        .base_url
-        .unwrap_or(config.get_openai_base_url().clone().unwrap_or(
-            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string()),
-        ));
-    let credentials = Credentials::new(key, base_url);
-    let model = args
+    let mut metadata = config.get_metadata().clone().unwrap_or_default();
+    metadata.extend(args.metadata);
+    let mut extra = RequestExtra::default()
+        .transport(transport.clone())
+        .user(args.user.or(config.get_user().clone()))
+        .metadata(metadata)
+        .headers(config.get_headers().clone().unwrap_or_default())
+        .no_train(no_train, credentials.base_url());
+    let template = args.template.as_deref().map(templates::load).transpose()?;
+    let mut model = args
+        .common
         .model
-        .unwrap_or(config.get_default_model().clone().unwrap_or(DEFAULT_MODEL))
+        .clone()
+        .or(template
+            .as_ref()
+            .and_then(|template| template.model.clone()))
+        .unwrap_or(default_model)
         .as_str();
 
-    let role = if !model.starts_with("o1") {
-        ChatCompletionMessageRole::System
-    } else {
-        ChatCompletionMessageRole::User
+    // What the command line, the template and `/set` chose; the rest comes from the
+    // `model_params` of whichever model is in use, resolved again on every switch.
+    let mut overrides = Sampling {
+        temperature: args.temperature,
+        top_p: args.top_p,
+        max_tokens: args.max_tokens,
+        reasoning_effort: args.reasoning_effort,
+        n: args.choices,
+    }
+    .or(template
+        .as_ref()
+        .map(Template::sampling)
+        .unwrap_or_default());
+    let mut sampling = overrides.clone().or(config.sampling(model));
+
+    let mut persona = args.preset.clone();
+    let seed = match args.preset {
+        Some(name) => config.preset(&name)?,
+        None => args.general.unwrap_or(
+            config
+                .get_system_prompt()
+                .clone()
+                .unwrap_or(String::from(SEED_PROMPT)),
+        ),
     };
 
     let mut messages = vec![ChatCompletionMessage {
-        role: role,
-        content: Some(args.general.unwrap_or(String::from(SEED_PROMPT))),
+        role: ChatCompletionMessageRole::System,
+        content: Some(seed),
         name: None,
         function_call: None,
         tool_call_id: None,
         tool_calls: Vec::new(),
     }];
 
+    if let Some(template) = &template {
+        let mut values = HashMap::new();
+        for (name, value) in args.vars {
+            values.insert(name, templates::value(&value)?);
+        }
+        let seeded = template.render(&values)?;
+        if seeded
+            .iter()
+            .any(|message| message.role == ChatCompletionMessageRole::System)
+        {
+            messages.clear();
+        }
+        messages.extend(seeded);
+    }
+
     if let Some(path) = args.file {
-        let mut input = String::new();
-        let _ = File::open(path)?.read_to_string(&mut input);
+        let input = document::read_text(Path::new(&path))?;
+        for part in document::parts(&path, &input, FILE_PART_TOKENS) {
+            messages.push(ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(part),
+                name: None,
+                function_call: None,
+                tool_call_id: None,
+                tool_calls: Vec::new(),
+            })
+        }
+    }
+
+    if let Some(dir) = args.dir {
+        let context = walk::load(
+            Path::new(&dir),
+            &args.include,
+            &args.exclude,
+            args.dir_tokens,
+        )?;
+        if context.files == 0 && context.skipped.is_empty() {
+            bail!("No files to load in {}", dir);
+        }
+        if !context.skipped.is_empty() {
+            eprintln!(
+                "Warning: loaded {} files of {}; {} more exceed the {} token budget and are only listed.",
+                context.files,
+                dir,
+                context.skipped.len(),
+                args.dir_tokens
+            );
+        }
         messages.push(ChatCompletionMessage {
             role: ChatCompletionMessageRole::User,
-            content: Some(input),
+            content: Some(context.text),
             name: None,
             function_call: None,
             tool_call_id: None,
@@ -90,89 +431,532 @@ async fn main() -> Result<()> {
         })
     }
 
-    let initial_state = messages.clone();
+    let summary_language = summary::language(config.get_summary_language().as_deref())?;
+    let mut initial_state = messages.clone();
 
-    loop {
-        let input = Text::new("").prompt()?;
-        match &input[..] {
-            "exit" => {
-                println!("Bye!");
-                return Ok(());
+    let mut journal = Journal::create()?;
+    if io::stdin().is_terminal() {
+        for interrupted in journal::interrupted()? {
+            let restore = Confirm::new(&format!(
+                "Restore the interrupted conversation from {}?",
+                interrupted.summary()
+            ))
+            .with_default(true)
+            .prompt()?;
+            if !restore {
+                if let Some(kept) = interrupted.discard()? {
+                    println!(
+                        "The messages replaced by summaries are kept in {}",
+                        file_link(&kept)
+                    );
+                }
+                continue;
             }
-            "reset" => {
-                messages = Vec::from(&initial_state[..]);
+            journal.restore(&interrupted)?;
+            initial_state = interrupted.messages[..interrupted.seed].to_vec();
+            messages = interrupted.messages;
+            if let Some(restored) = interrupted
+                .model
+                .as_deref()
+                .and_then(|name| Model::try_from(name).ok())
+            {
+                model = restored.as_str();
+                sampling = overrides.clone().or(config.sampling(model));
+                journal.sync(&messages)?;
+                journal.set_model(model)?;
+                println!("Model: {}", model);
             }
-            "v" => {
-                let input = Editor::new("Prompt:").prompt()?;
-                messages.push(ChatCompletionMessage {
-                    role: ChatCompletionMessageRole::User,
-                    content: Some(input),
-                    ..Default::default()
-                });
-                let stream = ChatCompletionDelta::builder(model, messages.clone())
-                    .credentials(credentials.clone())
-                    .create_stream()
-                    .await
-                    .with_context(|| "Can't open Stream")?;
-
-                let answer = ask(stream)
-                    .await?
-                    .choices
-                    .first()
-                    .with_context(|| "Can't get choices")?
-                    .message
-                    .clone();
-                messages.push(answer);
+            if messages.last().map(|m| m.role) == Some(ChatCompletionMessageRole::User) {
+                println!("The last message wasn't answered; /retry sends it.");
             }
-            "save" => {
-                let path = Text::new("path:").prompt()?;
-                let context = messages
-                    .clone()
-                    .into_iter()
-                    .filter(|m| m.role != ChatCompletionMessageRole::System)
-                    .filter_map(|m| {
-                        if m.role == ChatCompletionMessageRole::Assistant {
-                            m.content.map(|c| format!("Assistant:{}", c))
+            break;
+        }
+    }
+    journal.set_seed(initial_state.len())?;
+    journal.sync(&messages)?;
+
+    let terminal = TerminalIntegration::new(config.terminal_integration_enabled());
+    terminal.set_title(&title(model, persona.as_deref()));
+
+    // A template ending with a question is answered right away.
+    if template.is_some()
+        && messages == initial_state
+        && messages.last().map(|m| m.role) == Some(ChatCompletionMessageRole::User)
+    {
+        send(
+            &mut messages,
+            model,
+            &credentials,
+            &extra,
+            &sampling,
+            &config,
+            persona.as_deref(),
+            &mut journal,
+        )
+        .await?;
+        journal.sync(&messages)?;
+    }
+
+    let mut history = match config.history_enabled() {
+        true => History::load()?,
+        false => History::default(),
+    };
+    let completer = Completer::new(&commands::names())
+        .argument("/snippet", snippets::list().unwrap_or_default())
+        .argument(
+            "/persona",
+            config
+                .get_presets()
+                .iter()
+                .flat_map(|presets| presets.keys().cloned())
+                .collect(),
+        )
+        .argument(
+            "/set",
+            [
+                "temperature",
+                "top_p",
+                "max_tokens",
+                "reasoning_effort",
+                "n",
+            ]
+            .map(String::from)
+            .to_vec(),
+        )
+        .argument("/endpoint", vec![String::from("reset")])
+        .argument(
+            "/model",
+            Model::value_variants()
+                .iter()
+                .map(|model| model.as_str().to_string())
+                .collect(),
+        );
+    let mut compose = args.compose;
+    loop {
+        let mut answered = false;
+        terminal.prompt_start();
+        let prompt = prompt_line(
+            model,
+            Some(credentials.base_url()).filter(|endpoint| *endpoint != initial_endpoint),
+            compose,
+            &sampling,
+        );
+        let input = if compose {
+            input::read_compose(&prompt, config.fence_code_enabled(), &history, &completer)?
+                .trim_end()
+                .to_string()
+        } else {
+            input::read_line(&prompt, config.fence_code_enabled(), &history, &completer)?
+        };
+        if let Err(e) = history.add(&input) {
+            eprintln!("Can't save the input history: {:#}", e);
+        }
+        terminal.output_start();
+        match commands::parse(&input) {
+            Some((command, arguments)) => {
+                if command.kind != CommandKind::Exit {
+                    metrics::record_command(&config, command.name);
+                }
+                match command.kind {
+                    CommandKind::Help => println!("{}", commands::help()),
+                    CommandKind::Exit => {
+                        finish(journal)?;
+                        println!("Bye!");
+                        return Ok(());
+                    }
+                    CommandKind::Reset => {
+                        messages = Vec::from(&initial_state[..]);
+                    }
+                    CommandKind::Editor => {
+                        let input = Editor::new("Prompt:").prompt()?;
+                        match prompt::expand_references(&input, REFERENCE_TOKENS) {
+                            Ok(input) => {
+                                messages.push(ChatCompletionMessage {
+                                    role: ChatCompletionMessageRole::User,
+                                    content: Some(input),
+                                    ..Default::default()
+                                });
+                                answered = send(
+                                    &mut messages,
+                                    model,
+                                    &credentials,
+                                    &extra,
+                                    &sampling,
+                                    &config,
+                                    persona.as_deref(),
+                                    &mut journal,
+                                )
+                                .await?;
+                            }
+                            Err(e) => println!("{:#}", e),
+                        }
+                    }
+                    CommandKind::Save => {
+                        let path = Text::new("path:").prompt()?;
+                        let context = messages
+                            .clone()
+                            .into_iter()
+                            .filter(|m| {
+                                m.role != ChatCompletionMessageRole::System
+                                    || summary::is_summary(m)
+                            })
+                            .filter_map(|m| {
+                                if m.role == ChatCompletionMessageRole::Assistant {
+                                    m.content.map(|c| format!("Assistant:{}", c))
+                                } else {
+                                    m.content
+                                }
+                            })
+                            .collect::<Vec<String>>()
+                            .join("\n");
+                        let mut out = File::create(path)?;
+                        out.write_all(context.as_bytes())?;
+                        let exit = Confirm::new("Context successfully saved!\nexit?[y/n]:")
+                            .with_default(false)
+                            .prompt()?;
+                        if exit {
+                            finish(journal)?;
+                            println!("Bye!");
+                            return Ok(());
+                        }
+                    }
+                    CommandKind::Undo => match last_user_message(&messages, initial_state.len()) {
+                        Some(index) => {
+                            messages.truncate(index);
+                            println!("Removed the last exchange.");
+                        }
+                        None => println!("Nothing to undo."),
+                    },
+                    CommandKind::Edit => match last_user_message(&messages, initial_state.len()) {
+                        Some(index) => {
+                            let previous = messages[index].content.clone().unwrap_or_default();
+                            let input = Editor::new("Prompt:")
+                                .with_predefined_text(&previous)
+                                .prompt()?;
+                            messages.truncate(index);
+                            messages.push(ChatCompletionMessage {
+                                role: ChatCompletionMessageRole::User,
+                                content: Some(input),
+                                ..Default::default()
+                            });
+                            answered = send(
+                                &mut messages,
+                                model,
+                                &credentials,
+                                &extra,
+                                &sampling,
+                                &config,
+                                persona.as_deref(),
+                                &mut journal,
+                            )
+                            .await?;
+                        }
+                        None => println!("Nothing to edit."),
+                    },
+                    CommandKind::Retry => {
+                        let retry_model = match arguments.split_whitespace().collect::<Vec<_>>()[..]
+                        {
+                            [] => Ok(model),
+                            ["-m", name] => Model::try_from(name).map(|m| m.as_str()),
+                            _ => Err(anyhow!("Usage: {}", command)),
+                        };
+                        match retry_model {
+                            Ok(retry_model) => {
+                                // Put back on failure so the exchange can be retried again.
+                                let previous = messages.clone();
+                                if messages.len() > initial_state.len()
+                                    && messages.last().map(|m| m.role)
+                                        == Some(ChatCompletionMessageRole::Assistant)
+                                {
+                                    messages.pop();
+                                }
+                                if messages.len() > initial_state.len()
+                                    && messages.last().map(|m| m.role)
+                                        == Some(ChatCompletionMessageRole::User)
+                                {
+                                    answered = send(
+                                        &mut messages,
+                                        retry_model,
+                                        &credentials,
+                                        &extra,
+                                        &overrides.clone().or(config.sampling(retry_model)),
+                                        &config,
+                                        persona.as_deref(),
+                                        &mut journal,
+                                    )
+                                    .await?;
+                                } else {
+                                    println!("Nothing to retry.");
+                                }
+                                if !answered {
+                                    messages = previous;
+                                }
+                            }
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                    CommandKind::Set => {
+                        match arguments.split_whitespace().collect::<Vec<_>>()[..] {
+                            [] => println!("{}", sampling),
+                            [key, value] => match sampling
+                                .set(key, value)
+                                .and_then(|()| overrides.set(key, value))
+                            {
+                                Ok(()) => println!("{}", sampling),
+                                Err(e) => println!("{}", e),
+                            },
+                            _ => println!("Usage: {}", command),
+                        }
+                    }
+                    CommandKind::Bookmark => {
+                        let note = arguments.to_string();
+                        let answer = messages
+                            .iter()
+                            .rposition(|m| m.role == ChatCompletionMessageRole::Assistant)
+                            .filter(|index| *index >= initial_state.len());
+                        match answer {
+                            Some(index) => {
+                                let question =
+                                    last_user_message(&messages[..index], initial_state.len())
+                                        .and_then(|i| messages[i].content.clone())
+                                        .unwrap_or_default();
+                                Bookmarks::add(Bookmark::new(
+                                    model,
+                                    index,
+                                    question,
+                                    messages[index].content.clone().unwrap_or_default(),
+                                    Some(note).filter(|note| !note.is_empty()),
+                                ))?;
+                                println!("Bookmarked.");
+                            }
+                            None => println!("Nothing to bookmark."),
+                        }
+                    }
+                    CommandKind::Snippet => match arguments.split_whitespace().next() {
+                        Some(name) => match snippets::load(name) {
+                            Ok(snippet) => {
+                                let input = Editor::new("Prompt:")
+                                    .with_predefined_text(&snippet)
+                                    .prompt()?;
+                                messages.push(ChatCompletionMessage {
+                                    role: ChatCompletionMessageRole::User,
+                                    content: Some(input),
+                                    ..Default::default()
+                                });
+                                answered = send(
+                                    &mut messages,
+                                    model,
+                                    &credentials,
+                                    &extra,
+                                    &sampling,
+                                    &config,
+                                    persona.as_deref(),
+                                    &mut journal,
+                                )
+                                .await?;
+                            }
+                            Err(e) => println!("{}", e),
+                        },
+                        None => println!("Snippets: {}", snippets::list()?.join(", ")),
+                    },
+                    CommandKind::Prompts => {
+                        let query = arguments;
+                        let found = prompts::load()?
+                            .into_iter()
+                            .filter(|prompt| prompt.matches(query))
+                            .collect::<Vec<_>>();
+                        if found.is_empty() {
+                            println!(
+                        "No prompts found. Import a catalog with `ferrite prompts import <file>`."
+                    );
                         } else {
-                            m.content
+                            let prompt = Select::new("Prompt:", found).prompt()?;
+                            let values = prompt
+                                .variables
+                                .iter()
+                                .map(|name| Text::new(&format!("{}:", name)).prompt())
+                                .collect::<Result<Vec<_>, _>>()?;
+                            let input = Editor::new("Prompt:")
+                                .with_predefined_text(&prompt.fill(&values))
+                                .prompt()?;
+                            messages.push(ChatCompletionMessage {
+                                role: ChatCompletionMessageRole::User,
+                                content: Some(input),
+                                ..Default::default()
+                            });
+                            answered = send(
+                                &mut messages,
+                                model,
+                                &credentials,
+                                &extra,
+                                &sampling,
+                                &config,
+                                persona.as_deref(),
+                                &mut journal,
+                            )
+                            .await?;
                         }
-                    })
-                    .collect::<Vec<String>>()
-                    .join("\n");
-                let mut out = File::create(path)?;
-                out.write_all(context.as_bytes())?;
-                let exit = Confirm::new("Context successfully saved!\nexit?[y/n]:")
-                    .with_default(false)
-                    .prompt()?;
-                if exit {
-                    println!("Bye!");
-                    return Ok(());
+                    }
+                    CommandKind::Endpoint => match arguments.split_whitespace().collect::<Vec<_>>()
+                        [..]
+                    {
+                        [] => {
+                            println!("{}", link(credentials.base_url(), credentials.base_url()))
+                        }
+                        ["reset"] => {
+                            credentials =
+                                Credentials::new(credentials.api_key(), initial_endpoint.clone());
+                            extra = extra.no_train(no_train, credentials.base_url());
+                            println!(
+                                "Endpoint: {}",
+                                link(credentials.base_url(), credentials.base_url())
+                            );
+                        }
+                        [url] if url.starts_with("http://") || url.starts_with("https://") => {
+                            credentials = Credentials::new(credentials.api_key(), url);
+                            extra = extra.no_train(no_train, credentials.base_url());
+                            println!(
+                                "Endpoint: {}",
+                                link(credentials.base_url(), credentials.base_url())
+                            );
+                        }
+                        _ => println!("Usage: {}", command),
+                    },
+                    CommandKind::Compose => {
+                        compose = !compose;
+                        if compose {
+                            println!("Compose mode: Enter adds a line, Ctrl-D or Alt+Enter sends. /compose to leave.");
+                        } else {
+                            println!("Left compose mode.");
+                        }
+                    }
+                    CommandKind::Fetch => {
+                        match arguments.split_whitespace().collect::<Vec<_>>()[..] {
+                            [url] => match document::fetch(transport.as_ref(), url).await {
+                                Ok(page) => {
+                                    println!(
+                                        "Added {} (~{} tokens) to the conversation.",
+                                        link(url, url),
+                                        estimate_tokens(&page)
+                                    );
+                                    messages.push(ChatCompletionMessage {
+                                        role: ChatCompletionMessageRole::User,
+                                        content: Some(page),
+                                        ..Default::default()
+                                    });
+                                }
+                                Err(e) => println!("{:#}", e),
+                            },
+                            _ => println!("Usage: {}", command),
+                        }
+                    }
+                    CommandKind::Summarize if messages.len() > initial_state.len() => {
+                        match summary::generate_summary(
+                            &messages[initial_state.len()..],
+                            summary_language,
+                            model,
+                            &credentials,
+                            &extra,
+                            &config.retry_policy(),
+                        )
+                        .await
+                        {
+                            Ok(text) => println!("{}", text),
+                            Err(e) => println!("{:#}", e),
+                        }
+                    }
+                    CommandKind::Summarize => println!("Nothing to summarize."),
+                    CommandKind::Persona => match arguments.split_whitespace().next() {
+                        Some(name) => match config.preset(name) {
+                            Ok(seed) => {
+                                // A restored or template seed may start without a system message.
+                                if initial_state.first().map(|m| m.role)
+                                    == Some(ChatCompletionMessageRole::System)
+                                {
+                                    messages[0].content = Some(seed.clone());
+                                    initial_state[0].content = Some(seed);
+                                } else {
+                                    let system = ChatCompletionMessage {
+                                        role: ChatCompletionMessageRole::System,
+                                        content: Some(seed),
+                                        ..Default::default()
+                                    };
+                                    messages.insert(0, system.clone());
+                                    initial_state.insert(0, system);
+                                    journal.set_seed(initial_state.len())?;
+                                }
+                                persona = Some(name.to_string());
+                                terminal.set_title(&title(model, persona.as_deref()));
+                                println!("Switched to persona: {}", name);
+                            }
+                            Err(e) => println!("{}", e),
+                        },
+                        None => println!("Usage: {}", command),
+                    },
+                    CommandKind::Model => {
+                        match arguments.split_whitespace().collect::<Vec<_>>()[..] {
+                            [] => println!("{}", model),
+                            [name] => match Model::try_from(name) {
+                                Ok(switched) => {
+                                    model = switched.as_str();
+                                    sampling = overrides.clone().or(config.sampling(model));
+                                    journal.sync(&messages)?;
+                                    journal.set_model(model)?;
+                                    terminal.set_title(&title(model, persona.as_deref()));
+                                    println!("Switched to model: {}", model);
+                                }
+                                Err(e) => println!("{}", e),
+                            },
+                            _ => println!("Usage: {}", command),
+                        }
+                    }
                 }
             }
-            "" => {
-                println!("Empty message received. :(");
-            }
-            _ => {
-                messages.push(ChatCompletionMessage {
-                    role: ChatCompletionMessageRole::User,
-                    content: Some(input),
-                    ..Default::default()
-                });
-                let stream = ChatCompletionDelta::builder(model, messages.clone())
-                    .credentials(credentials.clone())
-                    .create_stream()
-                    .await
-                    .with_context(|| "Can't open Stream")?;
-
-                let answer = ask(stream)
-                    .await?
-                    .choices
-                    .first()
-                    .with_context(|| "Can't get choices")?
-                    .message
-                    .clone();
-                messages.push(answer);
+            None if input.is_empty() => println!("Empty message received. :("),
+            None => match commands::mistake(&input) {
+                Some(mistake) => println!("{}", mistake),
+                None => match prompt::expand_references(&input, REFERENCE_TOKENS) {
+                    Ok(input) => {
+                        messages.push(ChatCompletionMessage {
+                            role: ChatCompletionMessageRole::User,
+                            content: Some(input),
+                            ..Default::default()
+                        });
+                        answered = send(
+                            &mut messages,
+                            model,
+                            &credentials,
+                            &extra,
+                            &sampling,
+                            &config,
+                            persona.as_deref(),
+                            &mut journal,
+                        )
+                        .await?;
+                    }
+                    Err(e) => println!("{:#}", e),
+                },
+            },
+        }
+        journal.sync(&messages)?;
+        // Only a finished exchange can push the conversation over the budget.
+        if answered {
+            if let Err(e) = compact(
+                &mut messages,
+                initial_state.len(),
+                *config.get_compact_tokens(),
+                summary_language,
+                model,
+                &credentials,
+                &extra,
+                &config.retry_policy(),
+                &mut journal,
+            )
+            .await
+            {
+                println!("Can't compact the conversation: {:#}", e);
             }
         }
+        terminal.output_end();
     }
 }
@@ -0,0 +1,132 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use inquire::{Confirm, Editor};
+use openai::chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole};
+use std::io::Write;
+use std::process::{exit, Command, Stdio};
+use std::time::Instant;
+use FerriteChatter::{
+    cli::{bootstrap, CommonArgs},
+    core::{collect, open_stream, RequestExtra},
+    diff, metrics,
+};
+
+const COMMIT_PROMPT: &str = r#"
+You write git commit messages following the Conventional Commits specification.
+Given a staged diff, answer with the commit message only: a `type(scope): summary` subject line of at most 72 characters,
+a blank line, and a short body explaining what changed and why. Do not wrap the message in markdown.
+"#;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(flatten)]
+    common: CommonArgs,
+    /// Token budget for the diff sent to the model
+    #[clap(long = "max-diff-tokens", default_value_t = 12000)]
+    max_diff_tokens: usize,
+    /// Only print the generated message
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+}
+
+fn git(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .with_context(|| "Can't run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let (credentials, model, config, transport) = bootstrap(&args.common, "fcommit")?;
+
+    let staged = git(&["diff", "--cached"])?;
+    if staged.trim().is_empty() {
+        bail!("Nothing is staged. Use `git add` first.");
+    }
+    let stat = git(&["diff", "--cached", "--stat"])?;
+
+    let extra = RequestExtra::default()
+        .transport(transport.clone())
+        .user(config.get_user().clone())
+        .metadata(config.get_metadata().clone().unwrap_or_default())
+        .headers(config.get_headers().clone().unwrap_or_default())
+        .no_train(config.no_train_enabled(), credentials.base_url());
+
+    let model = model.as_str();
+
+    let messages = vec![
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::System,
+            content: Some(String::from(COMMIT_PROMPT)),
+            ..Default::default()
+        },
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(format!(
+                "{}\n{}",
+                stat,
+                diff::truncate(&staged, args.max_diff_tokens)
+            )),
+            ..Default::default()
+        },
+    ];
+
+    let started = Instant::now();
+    let stream = open_stream(
+        ChatCompletionDelta::builder(model, messages),
+        &credentials,
+        &extra,
+        &config.sampling(model),
+        &config.retry_policy(),
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
+    let generated = collect(stream)
+        .await?
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .with_context(|| "Can't get choices")?;
+    metrics::record_request(&config, model, started.elapsed());
+
+    if args.dry_run {
+        println!("{}", generated.trim());
+        return Ok(());
+    }
+
+    let message = Editor::new("Commit message:")
+        .with_predefined_text(generated.trim())
+        .prompt()?;
+    println!("{}", message.trim());
+    if message.trim().is_empty()
+        || !Confirm::new("Commit with this message?")
+            .with_default(true)
+            .prompt()?
+    {
+        return Ok(());
+    }
+
+    let mut commit = Command::new("git")
+        .args(["commit", "-F", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| "Can't run git")?;
+    commit
+        .stdin
+        .take()
+        .with_context(|| "Can't write to git")?
+        .write_all(message.as_bytes())?;
+    let status = commit.wait()?;
+    exit(status.code().unwrap_or(1))
+}
@@ -0,0 +1,367 @@
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use inquire::{Confirm, Editor, Password, PasswordDisplayMode};
+use serde_yaml::Value;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+use std::process;
+use FerriteChatter::{
+    bookmarks::Bookmarks,
+    config::{self, Config, ProjectConfig},
+    keyring,
+    metrics::Metrics,
+    prompts, snippets,
+    terminal::file_link,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Chat interactively (same as `fchat`)
+    #[clap(disable_help_flag = true)]
+    Chat {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Ask a single question (same as `fask`)
+    #[clap(disable_help_flag = true)]
+    Ask {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Translate between Japanese and English (same as `ftrans`)
+    #[clap(disable_help_flag = true)]
+    Trans {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Write a commit message for the staged changes (same as `fcommit`)
+    #[clap(disable_help_flag = true)]
+    Commit {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Turn a request into a shell command (same as `fsh`)
+    #[clap(disable_help_flag = true)]
+    Sh {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Show locally aggregated usage metrics (enable with `metrics: true`)
+    Metrics,
+    /// List answers saved with `/bookmark`, optionally only those containing QUERY
+    Bookmarks {
+        query: Option<String>,
+        /// Print the full questions and answers
+        #[clap(long = "full")]
+        full: bool,
+    },
+    /// Manage snippets inserted into fchat prompts with `/snippet <name>`
+    Snippet {
+        #[clap(subcommand)]
+        command: SnippetCommand,
+    },
+    /// Manage prompt catalogs used with `/prompts` in fchat
+    Prompts {
+        #[clap(subcommand)]
+        command: PromptsCommand,
+    },
+    /// View and edit settings of the config file
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage the API key stored in the OS keyring
+    Auth {
+        #[clap(subcommand)]
+        command: AuthCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print a setting (dotted for nested ones, e.g. presets.reviewer)
+    Get { key: String },
+    /// Change a setting; VALUE is YAML (e.g. 0.7, true, gpt-4o)
+    Set { key: String, value: String },
+    /// Print the settings in the config file
+    List,
+    /// Edit the config file in the editor and check it before saving
+    Edit,
+    /// Check the config file (and the project's .ferrite.yaml) for mistakes
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuthCommand {
+    /// Store the OpenAI API key in the keyring (Keychain or Secret Service)
+    Login,
+    /// Remove the API key from the keyring
+    Logout,
+    /// Show where the API key comes from
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+enum PromptsCommand {
+    /// List imported prompts, optionally only those matching QUERY
+    Ls { query: Option<String> },
+    /// Check a catalog (YAML list of name, description, prompt, variables, tags) and import it
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+enum SnippetCommand {
+    /// List saved snippets
+    Ls,
+    /// Print a snippet
+    Show { name: String },
+    /// Save a snippet from the argument, stdin or the editor
+    Add { name: String, text: Option<String> },
+    /// Edit a snippet in the editor
+    Edit { name: String },
+    /// Remove a snippet
+    Rm { name: String },
+}
+
+/// Runs one of the other binaries in place of this process, preferring the one
+/// installed next to `ferrite` over the one on the PATH.
+fn delegate(name: &str, args: &[OsString]) -> Result<()> {
+    let file = format!("{}{}", name, env::consts::EXE_SUFFIX);
+    let program = env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(&file)))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(&file));
+    let mut command = process::Command::new(&program);
+    command.args(args);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Only returns when the program couldn't be started.
+        let error = command.exec();
+        Err(error).with_context(|| format!("Can't run {}", program.display()))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command
+            .status()
+            .with_context(|| format!("Can't run {}", program.display()))?;
+        process::exit(status.code().unwrap_or(1))
+    }
+}
+
+/// `value` of the setting `key` with every secret in it, at any depth, replaced by
+/// `********`: API keys (also those of `profiles`), and tokens, passwords and
+/// authorization values such as those in `headers`.
+fn mask(key: &str, value: Value) -> Value {
+    let key = key.to_lowercase();
+    let secret = ["api_key", "authorization", "secret", "password"]
+        .iter()
+        .any(|word| key.contains(word))
+        || key.ends_with("token");
+    match value {
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .into_iter()
+                .map(|(name, value)| {
+                    let masked = mask(name.as_str().unwrap_or_default(), value);
+                    (name, masked)
+                })
+                .collect(),
+        ),
+        Value::Sequence(values) => {
+            Value::Sequence(values.into_iter().map(|value| mask(&key, value)).collect())
+        }
+        Value::Null => Value::Null,
+        _ if secret => Value::String(String::from("********")),
+        value => value,
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    match args.command {
+        Command::Chat { args } => delegate("fchat", &args)?,
+        Command::Ask { args } => delegate("fask", &args)?,
+        Command::Trans { args } => delegate("ftrans", &args)?,
+        Command::Commit { args } => delegate("fcommit", &args)?,
+        Command::Sh { args } => delegate("fsh", &args)?,
+        Command::Metrics => {
+            println!("{}", Metrics::load()?.report());
+        }
+        Command::Bookmarks { query, full } => {
+            let bookmarks = Bookmarks::load()?.bookmarks;
+            for (id, bookmark) in bookmarks.iter().enumerate() {
+                if query.as_ref().is_some_and(|query| !bookmark.matches(query)) {
+                    continue;
+                }
+                println!("{:>4} {}", id + 1, bookmark.summary());
+                if full {
+                    println!("\n{}\n\n{}\n", bookmark.question, bookmark.answer);
+                }
+            }
+        }
+        Command::Snippet { command } => match command {
+            SnippetCommand::Ls => {
+                for name in snippets::list()? {
+                    println!("{}", name);
+                }
+            }
+            SnippetCommand::Show { name } => {
+                println!("{}", snippets::load(&name)?);
+            }
+            SnippetCommand::Add { name, text } => {
+                let mut stdin = io::stdin();
+                let text = match text {
+                    Some(text) => text,
+                    None if !stdin.is_terminal() => {
+                        let mut s = String::new();
+                        stdin.read_to_string(&mut s)?;
+                        s
+                    }
+                    None => Editor::new(&format!("Snippet {}:", name)).prompt()?,
+                };
+                snippets::save(&name, &text)?;
+            }
+            SnippetCommand::Edit { name } => {
+                let text = Editor::new(&format!("Snippet {}:", name))
+                    .with_predefined_text(&snippets::load(&name)?)
+                    .prompt()?;
+                snippets::save(&name, &text)?;
+            }
+            SnippetCommand::Rm { name } => {
+                snippets::remove(&name)?;
+            }
+        },
+        Command::Prompts { command } => match command {
+            PromptsCommand::Ls { query } => {
+                for prompt in prompts::load()? {
+                    if query.as_ref().is_none_or(|query| prompt.matches(query)) {
+                        println!("{}", prompt);
+                    }
+                }
+            }
+            PromptsCommand::Import { file } => {
+                let count = prompts::import(&file)?;
+                println!("Imported {} prompts from {}.", count, file.display());
+            }
+        },
+        Command::Config { command } => {
+            let path = Config::path()?;
+            let text = if path.exists() {
+                read_to_string(&path).with_context(|| "Can't read config file")?
+            } else {
+                String::new()
+            };
+            match command {
+                ConfigCommand::Get { key } => match config::get_value(&text, &key)? {
+                    Some(value) => print!("{}", serde_yaml::to_string(&value)?),
+                    None => bail!("{} is not set", key),
+                },
+                ConfigCommand::Set { key, value } => {
+                    let updated = config::set_value(&text, &key, &value)?;
+                    if let Some(dir) = path.parent() {
+                        create_dir_all(dir)?;
+                    }
+                    write(&path, updated).with_context(|| "Can't write config file")?;
+                }
+                ConfigCommand::List => {
+                    for key in config::keys() {
+                        let Some(value) = config::get_value(&text, key)? else {
+                            continue;
+                        };
+                        let shown = match mask(key, value) {
+                            Value::String(s) => s,
+                            value => serde_json::to_string(&value)?,
+                        };
+                        println!("{}: {}", key, shown);
+                    }
+                    if let Err(e) = Config::validate(&text) {
+                        eprintln!("Warning: {:#}", e);
+                    }
+                }
+                ConfigCommand::Check => {
+                    Config::validate(&text)
+                        .with_context(|| format!("{} has problems", path.display()))?;
+                    println!("{}: OK", file_link(&path));
+                    if let Some(project) = config::find_project_config() {
+                        let text = read_to_string(&project)
+                            .with_context(|| format!("Can't read {}", project.display()))?;
+                        ProjectConfig::validate(&text)
+                            .with_context(|| format!("{} has problems", project.display()))?;
+                        println!("{}: OK", file_link(&project));
+                    }
+                }
+                ConfigCommand::Edit => {
+                    let mut edited = text;
+                    loop {
+                        edited = Editor::new("Config:")
+                            .with_predefined_text(&edited)
+                            .with_file_extension(".yaml")
+                            .prompt()?;
+                        match Config::validate(&edited) {
+                            Ok(()) => break,
+                            Err(e) => {
+                                println!("{:#}", e);
+                                if !Confirm::new("Edit again?").with_default(true).prompt()? {
+                                    bail!("The config file was not changed")
+                                }
+                            }
+                        }
+                    }
+                    if let Some(dir) = path.parent() {
+                        create_dir_all(dir)?;
+                    }
+                    write(&path, edited).with_context(|| "Can't write config file")?;
+                }
+            }
+        }
+        Command::Auth { command } => match command {
+            AuthCommand::Login => {
+                let mut stdin = io::stdin();
+                let key = if stdin.is_terminal() {
+                    Password::new("OpenAI API key:")
+                        .with_display_mode(PasswordDisplayMode::Masked)
+                        .without_confirmation()
+                        .prompt()?
+                } else {
+                    let mut s = String::new();
+                    stdin.read_to_string(&mut s)?;
+                    s
+                };
+                if key.trim().is_empty() {
+                    bail!("The API key is empty")
+                }
+                keyring::set(key.trim())?;
+                println!("Stored the API key in the keyring.");
+            }
+            AuthCommand::Logout => {
+                keyring::delete()?;
+                println!("Removed the API key from the keyring.");
+            }
+            AuthCommand::Status => {
+                let source = if keyring::get().is_some() {
+                    "keyring"
+                } else if Config::load()?.get_openai_api_key().is_some() {
+                    "config file"
+                } else if env::var("OPENAI_API_KEY").is_ok() {
+                    "OPENAI_API_KEY"
+                } else {
+                    "not set"
+                };
+                println!("API key: {}", source);
+            }
+        },
+    }
+    Ok(())
+}
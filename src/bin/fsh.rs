@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use inquire::Confirm;
+use openai::chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole};
+use std::env;
+use std::path::Path;
+use std::process::{exit, Command};
+use std::time::Instant;
+use FerriteChatter::{
+    cli::{bootstrap, CommonArgs},
+    core::{collect, open_stream, RequestExtra},
+    metrics,
+    prompt::{self, StdinPosition},
+    render::print_answer,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(flatten)]
+    common: CommonArgs,
+    /// Explain the generated command before asking to run it
+    #[clap(long = "explain", short = 'e')]
+    explain: bool,
+    /// Send piped input larger than `large_input_bytes` without asking
+    #[clap(long = "force-large-input")]
+    force_large_input: bool,
+    /// Where piped stdin goes relative to the prompt argument
+    #[clap(long = "stdin-position", value_enum, default_value = "before")]
+    stdin_position: StdinPosition,
+    /// What the command should do
+    prompt: Option<String>,
+}
+
+fn shell() -> String {
+    if cfg!(windows) {
+        String::from("powershell")
+    } else {
+        env::var("SHELL")
+            .ok()
+            .and_then(|shell| {
+                Path::new(&shell)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(String::from)
+            })
+            .unwrap_or_else(|| String::from("sh"))
+    }
+}
+
+/// Removes a surrounding markdown code fence in case the model added one anyway.
+fn strip_fence(answer: &str) -> String {
+    let answer = answer.trim();
+    match answer.strip_prefix("```") {
+        Some(fenced) => fenced
+            .split_once('\n')
+            .map(|(_, body)| body)
+            .unwrap_or_default()
+            .trim_end_matches("```")
+            .trim()
+            .to_string(),
+        None => answer.to_string(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let (credentials, model, config, transport) = bootstrap(&args.common, "fsh")?;
+
+    let piped = prompt::read_stdin(args.stdin_position);
+
+    let extra = RequestExtra::default()
+        .transport(transport.clone())
+        .user(config.get_user().clone())
+        .metadata(config.get_metadata().clone().unwrap_or_default())
+        .headers(config.get_headers().clone().unwrap_or_default())
+        .no_train(config.no_train_enabled(), credentials.base_url());
+
+    let model = model.as_str();
+    let sampling = config.sampling(model);
+
+    if let Some(input) = &piped {
+        prompt::confirm_large_input(
+            input,
+            config.large_input_limit(),
+            args.force_large_input,
+            config.input_price(model),
+        )?;
+    }
+    let prompt = prompt::compose(piped, args.prompt, args.stdin_position)?;
+
+    let shell = shell();
+    let messages = vec![
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::System,
+            content: Some(format!(
+                "Translate the user's request into a single {} command for {}. \
+                 Reply with the command only: no markdown, no explanation.",
+                shell,
+                env::consts::OS
+            )),
+            ..Default::default()
+        },
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(prompt),
+            ..Default::default()
+        },
+    ];
+
+    let started = Instant::now();
+    let stream = open_stream(
+        ChatCompletionDelta::builder(model, messages),
+        &credentials,
+        &extra,
+        &sampling,
+        &config.retry_policy(),
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
+    let answer = collect(stream)
+        .await?
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .with_context(|| "Can't get choices")?;
+    metrics::record_request(&config, model, started.elapsed());
+
+    let command = strip_fence(&answer);
+    println!("$ {}", command);
+
+    if args.explain {
+        let messages = vec![
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some(format!(
+                    "Briefly explain what this {} command does and point out anything destructive.",
+                    shell
+                )),
+                ..Default::default()
+            },
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(command.clone()),
+                ..Default::default()
+            },
+        ];
+        let stream = open_stream(
+            ChatCompletionDelta::builder(model, messages),
+            &credentials,
+            &extra,
+            &sampling,
+            &config.retry_policy(),
+        )
+        .await
+        .with_context(|| "Can't open Stream")?;
+        print_answer(stream).await?;
+    }
+
+    if !Confirm::new("Run this command?")
+        .with_default(false)
+        .prompt()?
+    {
+        return Ok(());
+    }
+
+    let flag = if shell == "powershell" {
+        "-Command"
+    } else {
+        "-c"
+    };
+    let status = Command::new(&shell)
+        .arg(flag)
+        .arg(&command)
+        .status()
+        .with_context(|| format!("Can't run {}", shell))?;
+    exit(status.code().unwrap_or(1))
+}
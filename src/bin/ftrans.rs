@@ -1,97 +1,589 @@
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::{bail, Context, Result};
+use clap::{ArgGroup, Parser};
 use openai::{
-    chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole},
+    chat::{
+        ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole,
+        ChatCompletionResponseFormat,
+    },
     Credentials,
 };
-use std::env;
-use std::io::{self, IsTerminal, Read};
+use serde::Deserialize;
+use std::fs::{read_to_string, write};
+use std::io::{stdin, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use FerriteChatter::{
+    chunk::{parts, Chunker, Part},
+    cli::{bootstrap, CommonArgs},
+    comments::{self, Syntax},
+    completion::Completer,
     config::Config,
-    core::{ask, Model, DEFAULT_MODEL},
+    core::{collect, open_stream, parse_key_value, RequestExtra, Sampling},
+    glossary::Glossary,
+    history::History,
+    input,
+    language::{Direction, LANGUAGES},
+    metrics,
+    prompt::{self, StdinPosition},
+    render::print_answer,
+    retry::RetryPolicy,
+    subtitles::Subtitles,
 };
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(group(ArgGroup::new("source").args(["code", "file", "srt"])))]
 struct Args {
+    #[clap(flatten)]
+    common: CommonArgs,
     /// Open Prompt(General Prompt)
-    #[clap(long = "general", short = 'g')]
+    #[clap(long = "general", short = 'g', conflicts_with_all = ["from", "to"])]
     general: Option<String>,
-    /// OpenAI API Key
-    #[clap(long = "key", short = 'k')]
-    key: Option<String>,
-    /// OpenAI API Base URL
-    #[clap(long = "base-url", short = 'b')]
-    base_url: Option<String>,
-    /// OpenAI Model
-    #[clap(long = "model", short = 'm', value_enum, default_value = "gpt-4o")]
-    model: Option<Model>,
+    /// Language to translate from, by code or name (detected when omitted)
+    #[clap(long = "from")]
+    from: Option<String>,
+    /// Language to translate into, by code or name (`translate_to` in the config by default)
+    #[clap(long = "to")]
+    to: Option<String>,
+    /// Glossary of fixed term translations (`glossary` in the config by default)
+    #[clap(long = "glossary")]
+    glossary: Option<PathBuf>,
+    /// List the languages --from and --to know
+    #[clap(long = "list-languages")]
+    list_languages: bool,
+    /// End-user identifier sent as the `user` request field
+    #[clap(long = "user")]
+    user: Option<String>,
+    /// Request metadata (key=value, repeatable)
+    #[clap(long = "metadata", value_parser = parse_key_value)]
+    metadata: Vec<(String, String)>,
+    /// Ask the provider not to store the conversation or train on it
+    #[clap(long = "no-train")]
+    no_train: bool,
+    /// Translate only the comments and i18n strings of a source file, keeping the code intact
+    #[clap(long = "code", conflicts_with = "prompt")]
+    code: Option<String>,
+    /// Translate a whole file, keeping its Markdown structure and fenced code intact
+    #[clap(
+        long = "file",
+        short = 'f',
+        conflicts_with_all = ["prompt", "code", "general"]
+    )]
+    file: Option<PathBuf>,
+    /// Translate the cues of an SRT or WebVTT file, keeping indices and timestamps intact
+    #[clap(
+        long = "srt",
+        visible_alias = "vtt",
+        conflicts_with_all = ["prompt", "code", "file", "general"]
+    )]
+    srt: Option<PathBuf>,
+    /// Write the translation of --file, --code or --srt to a file instead of stdout
+    #[clap(long = "output", short = 'o', requires = "source")]
+    output: Option<PathBuf>,
+    /// Send piped input larger than `large_input_bytes` without asking
+    #[clap(long = "force-large-input")]
+    force_large_input: bool,
+    /// Where piped stdin goes relative to the prompt argument
+    #[clap(long = "stdin-position", value_enum, default_value = "before")]
+    stdin_position: StdinPosition,
     /// Prompt
     prompt: Option<String>,
 }
 
+/// `{direction}` is replaced with `Direction::instruction`.
+const CODE_PROMPT: &str = r#"
+You translate comments and UI strings extracted from source code.
+You receive a JSON object {"segments": [...]}. Translate each element. {direction}
+Keep identifiers, URLs, placeholders such as {name} or %s, and escape sequences exactly as they are.
+Answer with a JSON object {"segments": [...]} that has the same number of elements in the same order.
+"#;
+
+/// Number of comments sent per request in `--code` mode.
+const CODE_BATCH: usize = 100;
+
+/// `{direction}` is replaced with `Direction::instruction`.
+const FILE_PROMPT: &str = r#"
+You translate a part of a Markdown document.
+Translate the text. {direction}
+Keep the Markdown structure exactly as it is: headings, lists, tables, links, inline code, HTML tags and line breaks.
+Answer with the translated Markdown only, without wrapping it in a code fence.
+"#;
+
+/// Token budget of the prose sent per request in `--file` mode.
+const FILE_CHUNK_TOKENS: usize = 3000;
+
+/// `{direction}` is replaced with `Direction::instruction`.
+const SUBTITLE_PROMPT: &str = r#"
+You translate subtitles.
+You receive a JSON object {"segments": [...]} where each element is the text of one cue, in order. Translate each element. {direction}
+Keep line breaks inside an element and formatting tags such as <i> as they are, and keep each cue short enough to read.
+Answer with a JSON object {"segments": [...]} that has the same number of elements in the same order.
+"#;
+
+/// Number of cues sent per request in `--srt` mode.
+const SUBTITLE_BATCH: usize = 50;
+
+#[derive(Deserialize)]
+struct Segments {
+    segments: Vec<String>,
+}
+
+/// Translates `texts` in batches of `batch` as a JSON array, with `prompt` as the system
+/// prompt, and returns the translations in the same order.
+#[allow(clippy::too_many_arguments)]
+async fn translate_segments(
+    texts: &[&str],
+    prompt: &str,
+    batch: usize,
+    glossary: &Glossary,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<Vec<String>> {
+    let mut translations = Vec::with_capacity(texts.len());
+    for texts in texts.chunks(batch) {
+        let messages = vec![
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some(glossary.apply(prompt, &texts.join("\n"))),
+                ..Default::default()
+            },
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(serde_json::json!({ "segments": texts }).to_string()),
+                ..Default::default()
+            },
+        ];
+        let stream = open_stream(
+            ChatCompletionDelta::builder(model, messages)
+                .response_format(ChatCompletionResponseFormat::json_object()),
+            credentials,
+            extra,
+            sampling,
+            policy,
+        )
+        .await
+        .with_context(|| "Can't open Stream")?;
+        let answer = collect(stream)
+            .await?
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .with_context(|| "Can't get choices")?;
+        let translated: Segments =
+            serde_json::from_str(&answer).with_context(|| "Can't parse the translated segments")?;
+        if translated.segments.len() != texts.len() {
+            bail!(
+                "Expected {} translated segments but got {}",
+                texts.len(),
+                translated.segments.len()
+            );
+        }
+        glossary.check(&texts.join("\n"), &translated.segments.join("\n"));
+        translations.extend(translated.segments);
+    }
+    Ok(translations)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn translate_code(
+    path: &str,
+    direction: &Direction,
+    glossary: &Glossary,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<String> {
+    let source = read_to_string(path).with_context(|| format!("Can't read {}", path))?;
+    let syntax = Syntax::from_path(Path::new(path))
+        .with_context(|| format!("Unsupported source file: {}", path))?;
+    let segments = comments::extract(&source, &syntax);
+    let texts = segments
+        .iter()
+        .map(|segment| &source[segment.start..segment.end])
+        .collect::<Vec<_>>();
+    let translations = translate_segments(
+        &texts,
+        &CODE_PROMPT.replace("{direction}", &direction.instruction()),
+        CODE_BATCH,
+        glossary,
+        model,
+        credentials,
+        extra,
+        sampling,
+        policy,
+    )
+    .await?;
+    Ok(comments::replace(&source, &segments, &translations))
+}
+
+/// Translates the text of the cues of an SRT or WebVTT file, keeping the rest.
+#[allow(clippy::too_many_arguments)]
+async fn translate_subtitles(
+    path: &Path,
+    direction: &Direction,
+    glossary: &Glossary,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<String> {
+    let source = read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+    let mut subtitles = Subtitles::parse(&source);
+    let translations = translate_segments(
+        &subtitles.texts(),
+        &SUBTITLE_PROMPT.replace("{direction}", &direction.instruction()),
+        SUBTITLE_BATCH,
+        glossary,
+        model,
+        credentials,
+        extra,
+        sampling,
+        policy,
+    )
+    .await?;
+    subtitles.replace(translations);
+    Ok(subtitles.render())
+}
+
+/// Translates the prose of a document chunk by chunk; fenced code is copied as it is.
+#[allow(clippy::too_many_arguments)]
+async fn translate_file(
+    path: &Path,
+    direction: &Direction,
+    glossary: &Glossary,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<String> {
+    let source = read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+    let chunker = Chunker::new(FILE_CHUNK_TOKENS, 0);
+    let pieces = parts(&source)
+        .into_iter()
+        .flat_map(|part| match part {
+            Part::Code(code) => vec![Part::Code(code)],
+            Part::Prose(prose) => chunker
+                .split(&prose)
+                .into_iter()
+                .map(|chunk| Part::Prose(chunk.text))
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+    let total = pieces
+        .iter()
+        .filter(|piece| matches!(piece, Part::Prose(prose) if !prose.trim().is_empty()))
+        .count();
+
+    let mut translated = String::with_capacity(source.len());
+    let mut done = 0;
+    for piece in pieces {
+        let prose = match piece {
+            Part::Prose(prose) if !prose.trim().is_empty() => prose,
+            Part::Prose(text) | Part::Code(text) => {
+                translated.push_str(&text);
+                continue;
+            }
+        };
+        done += 1;
+        if total > 1 {
+            eprintln!("[{}/{}] {}", done, total, path.display());
+        }
+        let messages = vec![
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some(glossary.apply(
+                    &FILE_PROMPT.replace("{direction}", &direction.instruction()),
+                    &prose,
+                )),
+                ..Default::default()
+            },
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(prose.trim().to_string()),
+                ..Default::default()
+            },
+        ];
+        let stream = open_stream(
+            ChatCompletionDelta::builder(model, messages),
+            credentials,
+            extra,
+            sampling,
+            policy,
+        )
+        .await
+        .with_context(|| "Can't open Stream")?;
+        let answer = collect(stream)
+            .await?
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .with_context(|| "Can't get choices")?;
+        glossary.check(&prose, &answer);
+        // The blank lines around a chunk separate it from the fences and chunks next to it.
+        let leading = &prose[..prose.len() - prose.trim_start().len()];
+        let trailing = &prose[prose.trim_end().len()..];
+        translated.push_str(leading);
+        translated.push_str(answer.trim());
+        translated.push_str(trailing);
+    }
+    Ok(translated)
+}
+
+/// Exchanges kept as context in the REPL, so terminology stays consistent.
+const REPL_CONTEXT: usize = 3;
+
+/// Translates each line typed until `exit` or Ctrl-D. `/to` and `/from` change the
+/// direction, which also drops the context.
+#[allow(clippy::too_many_arguments)]
+async fn repl(
+    mut direction: Direction,
+    glossary: &Glossary,
+    general: Option<String>,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    config: &Config,
+) -> Result<()> {
+    let codes = LANGUAGES
+        .iter()
+        .map(|(code, _)| code.to_string())
+        .collect::<Vec<_>>();
+    let completer = Completer::new(&["/to", "/from", "exit"])
+        .argument("/to", codes.clone())
+        .argument(
+            "/from",
+            codes.into_iter().chain([String::from("auto")]).collect(),
+        );
+    let mut history = History::default();
+    let mut context: Vec<ChatCompletionMessage> = Vec::new();
+
+    loop {
+        let prompt = format!("{}> ", direction);
+        let Ok(input) = input::read_line(&prompt, false, &history, &completer) else {
+            return Ok(());
+        };
+        history.add(&input)?;
+        let input = input.trim();
+        let changed = match input.split_once(' ').unwrap_or((input, "")) {
+            ("", _) => continue,
+            ("exit" | "/exit" | "/quit", "") => return Ok(()),
+            ("/to", language) => Direction::new(direction.from, Some(language.trim())),
+            ("/from", "auto") => Direction::new(None, direction.to),
+            ("/from", language) => Direction::new(Some(language.trim()), direction.to),
+            _ => {
+                let mut messages = vec![ChatCompletionMessage {
+                    role: ChatCompletionMessageRole::System,
+                    content: Some(
+                        glossary
+                            .apply(&general.clone().unwrap_or(direction.system_prompt()), input),
+                    ),
+                    ..Default::default()
+                }];
+                messages.extend(context.iter().cloned());
+                messages.push(ChatCompletionMessage {
+                    role: ChatCompletionMessageRole::User,
+                    content: Some(input.to_string()),
+                    ..Default::default()
+                });
+
+                let started = Instant::now();
+                let stream = open_stream(
+                    ChatCompletionDelta::builder(model, messages.clone()),
+                    credentials,
+                    extra,
+                    sampling,
+                    &config.retry_policy(),
+                )
+                .await
+                .with_context(|| "Can't open Stream")?;
+                let answer = print_answer(stream)
+                    .await?
+                    .choices
+                    .first()
+                    .and_then(|choice| choice.message.content.clone())
+                    .with_context(|| "Can't get choices")?;
+                metrics::record_request(config, model, started.elapsed());
+                glossary.check(input, &answer);
+
+                context.extend(messages.pop());
+                context.push(ChatCompletionMessage {
+                    role: ChatCompletionMessageRole::Assistant,
+                    content: Some(answer),
+                    ..Default::default()
+                });
+                if context.len() > REPL_CONTEXT * 2 {
+                    context.drain(..context.len() - REPL_CONTEXT * 2);
+                }
+                continue;
+            }
+        };
+        match changed {
+            Ok(changed) => {
+                direction = changed;
+                context.clear();
+            }
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+}
+
+/// Writes to `output`, or prints when there's none.
+fn emit(output: Option<&Path>, text: &str) -> Result<()> {
+    match output {
+        Some(path) => write(path, text).with_context(|| format!("Can't write {}", path.display())),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let config = Config::load()?;
-
-    let key = args.key.unwrap_or(
-        config.get_openai_api_key().clone().unwrap_or(
-            env::var("OPENAI_API_KEY")
-                .with_context(|| "You need to set API key to the `OPENAI_API_KEY`")?,
-        ),
-    );
-    let base_url = args
-        .base_url
-        .unwrap_or(config.get_openai_base_url().clone().unwrap_or(
-            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string()),
-        ));
-    let credentials = Credentials::new(key, base_url);
-
-    let model = args
-        .model
-        .unwrap_or(config.get_default_model().clone().unwrap_or(DEFAULT_MODEL))
-        .as_str();
-
-    let role = if !model.starts_with("o1") {
-        ChatCompletionMessageRole::System
-    } else {
-        ChatCompletionMessageRole::User
+    if args.list_languages {
+        for (code, name) in LANGUAGES {
+            println!("{}  {}", code, name);
+        }
+        return Ok(());
+    }
+    let (credentials, model, config, transport) = bootstrap(&args.common, "ftrans")?;
+    let direction = Direction::new(
+        args.from.as_deref(),
+        args.to.as_deref().or(config.get_translate_to().as_deref()),
+    )?;
+    let glossary = match args
+        .glossary
+        .or(config.get_glossary().clone().map(PathBuf::from))
+    {
+        Some(path) => Glossary::load(&path)?,
+        None => Glossary::default(),
     };
 
-    let mut messages = vec![ChatCompletionMessage {
-        role: role,
-        content: Some(args.general.unwrap_or(String::from(
-            "これから渡す文章が、日本語の場合は英語に翻訳し、英語など日本語以外の場合は日本語に翻訳してください。",
-        ))),
-        ..Default::default()
-    }];
-
-    let mut stdin = io::stdin();
-    let prompt = if !stdin.is_terminal() {
-        let mut s = String::new();
-        let _ = stdin.read_to_string(&mut s);
-        Some(format!(
-            "{}\n{}",
-            s,
-            args.prompt.unwrap_or(String::default())
-        ))
-    } else {
-        args.prompt
+    let mut metadata = config.get_metadata().clone().unwrap_or_default();
+    metadata.extend(args.metadata);
+    let extra = RequestExtra::default()
+        .transport(transport.clone())
+        .user(args.user.or(config.get_user().clone()))
+        .metadata(metadata)
+        .headers(config.get_headers().clone().unwrap_or_default())
+        .no_train(
+            args.no_train || config.no_train_enabled(),
+            credentials.base_url(),
+        );
+
+    let model = model.as_str();
+    let sampling = config.sampling(model);
+
+    if let Some(path) = args.code {
+        let translated = translate_code(
+            &path,
+            &direction,
+            &glossary,
+            model,
+            &credentials,
+            &extra,
+            &sampling,
+            &config.retry_policy(),
+        )
+        .await?;
+        return emit(args.output.as_deref(), &translated);
+    }
+    if let Some(path) = args.file {
+        let translated = translate_file(
+            &path,
+            &direction,
+            &glossary,
+            model,
+            &credentials,
+            &extra,
+            &sampling,
+            &config.retry_policy(),
+        )
+        .await?;
+        return emit(args.output.as_deref(), &translated);
+    }
+    if let Some(path) = args.srt {
+        let translated = translate_subtitles(
+            &path,
+            &direction,
+            &glossary,
+            model,
+            &credentials,
+            &extra,
+            &sampling,
+            &config.retry_policy(),
+        )
+        .await?;
+        return emit(args.output.as_deref(), &translated);
     }
-    .with_context(|| "Please provide input via a pipe or pass the prompt as an argument.")?;
 
-    messages.push(ChatCompletionMessage {
-        role: ChatCompletionMessageRole::User,
-        content: Some(prompt),
-        ..Default::default()
-    });
+    let piped = prompt::read_stdin(args.stdin_position);
+    if piped.is_none() && args.prompt.is_none() && stdin().is_terminal() {
+        return repl(
+            direction,
+            &glossary,
+            args.general,
+            model,
+            &credentials,
+            &extra,
+            &sampling,
+            &config,
+        )
+        .await;
+    }
 
-    let stream = ChatCompletionDelta::builder(model, messages.clone())
-        .credentials(credentials.clone())
-        .create_stream()
-        .await
-        .with_context(|| "Can't open Stream")?;
+    if let Some(input) = &piped {
+        prompt::confirm_large_input(
+            input,
+            config.large_input_limit(),
+            args.force_large_input,
+            config.input_price(model),
+        )?;
+    }
+    let prompt = prompt::compose(piped, args.prompt, args.stdin_position)?;
+
+    let messages = vec![
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::System,
+            content: Some(
+                glossary.apply(&args.general.unwrap_or(direction.system_prompt()), &prompt),
+            ),
+            ..Default::default()
+        },
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(prompt.clone()),
+            ..Default::default()
+        },
+    ];
+
+    let started = Instant::now();
+    let stream = open_stream(
+        ChatCompletionDelta::builder(model, messages.clone()),
+        &credentials,
+        &extra,
+        &sampling,
+        &config.retry_policy(),
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
 
-    ask(stream).await.map(|_| ())
+    let answer = print_answer(stream)
+        .await?
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .unwrap_or_default();
+    metrics::record_request(&config, model, started.elapsed());
+    glossary.check(&prompt, &answer);
+    Ok(())
 }
@@ -0,0 +1,135 @@
+use crate::config::data_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An answer worth keeping, saved with `/bookmark` in fchat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    /// Unix time in seconds.
+    pub saved_at: u64,
+    pub model: String,
+    /// Position of the answer in the conversation it was saved from.
+    pub message: usize,
+    pub question: String,
+    pub answer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Bookmark {
+    pub fn new(
+        model: &str,
+        message: usize,
+        question: String,
+        answer: String,
+        note: Option<String>,
+    ) -> Self {
+        Self {
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            model: model.to_string(),
+            message,
+            question,
+            answer,
+            note,
+        }
+    }
+
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [&self.question, &self.answer]
+            .into_iter()
+            .chain(self.note.as_ref())
+            .any(|text| text.to_lowercase().contains(&query))
+    }
+
+    /// One-line summary: date, model, note and the beginning of the question.
+    pub fn summary(&self) -> String {
+        let question = self
+            .question
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut question = question.chars().take(60).collect::<String>();
+        if self.question.chars().count() > 60 {
+            question.push('…');
+        }
+        match &self.note {
+            Some(note) => format!(
+                "{} {} [{}] {}",
+                date(self.saved_at),
+                self.model,
+                note,
+                question
+            ),
+            None => format!("{} {} {}", date(self.saved_at), self.model, question),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Bookmarks {
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn path() -> Result<PathBuf> {
+        Ok(data_dir()?.join("bookmarks.yaml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            Ok(Self::default())
+        } else {
+            serde_yaml::from_str(
+                &read_to_string(path).with_context(|| "Can't read bookmarks file")?,
+            )
+            .with_context(|| "Can't parse bookmarks file")
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).with_context(|| "Can't create data directory")?;
+        }
+        write(path, serde_yaml::to_string(self)?).with_context(|| "Can't write bookmarks file")
+    }
+
+    pub fn add(bookmark: Bookmark) -> Result<()> {
+        let mut bookmarks = Self::load()?;
+        bookmarks.bookmarks.push(bookmark);
+        bookmarks.save()
+    }
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC.
+pub fn date(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let minutes = (secs % 86400) / 60;
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
@@ -0,0 +1,131 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Chat requests and the events streamed back for them, written by `--record` and
+/// answered from by `--replay` so that runs can be repeated without the API.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    /// The request body as sent, after sampling and extra fields were applied.
+    pub request: Value,
+    /// The `data` of every server-sent event, up to and including `[DONE]`.
+    pub events: Vec<String>,
+    /// Set while replaying once the interaction was answered.
+    #[serde(skip)]
+    used: bool,
+}
+
+enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+struct State {
+    mode: Mode,
+    cassette: Mutex<Cassette>,
+}
+
+static STATE: OnceLock<State> = OnceLock::new();
+
+/// Starts recording to `record` (replacing it) or replaying from `replay`.
+pub fn init(record: Option<&Path>, replay: Option<&Path>) -> Result<()> {
+    let state = match (record, replay) {
+        (Some(_), Some(_)) => bail!("Can't record and replay at the same time"),
+        (Some(path), None) => State {
+            mode: Mode::Record(path.to_path_buf()),
+            cassette: Mutex::default(),
+        },
+        (None, Some(path)) => {
+            let text = read_to_string(path)
+                .with_context(|| format!("Can't read cassette {}", path.display()))?;
+            State {
+                mode: Mode::Replay(path.to_path_buf()),
+                cassette: Mutex::new(
+                    serde_json::from_str(&text)
+                        .with_context(|| format!("Can't parse cassette {}", path.display()))?,
+                ),
+            }
+        }
+        (None, None) => return Ok(()),
+    };
+    let _ = STATE.set(state);
+    Ok(())
+}
+
+pub fn recording() -> bool {
+    matches!(
+        STATE.get(),
+        Some(State {
+            mode: Mode::Record(_),
+            ..
+        })
+    )
+}
+
+pub fn replaying() -> bool {
+    matches!(
+        STATE.get(),
+        Some(State {
+            mode: Mode::Replay(_),
+            ..
+        })
+    )
+}
+
+/// Adds an interaction to the cassette being recorded and saves it.
+pub fn record(request: Value, events: Vec<String>) -> Result<()> {
+    let Some(State {
+        mode: Mode::Record(path),
+        cassette,
+    }) = STATE.get()
+    else {
+        return Ok(());
+    };
+    let mut cassette = cassette
+        .lock()
+        .map_err(|_| anyhow!("Cassette lock poisoned"))?;
+    cassette.interactions.push(Interaction {
+        request,
+        events,
+        used: false,
+    });
+    write(path, serde_json::to_string_pretty(&*cassette)?)
+        .with_context(|| format!("Can't write cassette {}", path.display()))
+}
+
+/// The recorded events for `request` when replaying. Identical requests are answered
+/// in the order they were recorded; a request that was never recorded is an error.
+pub fn replay(request: &Value) -> Result<Option<Vec<String>>> {
+    let Some(State {
+        mode: Mode::Replay(path),
+        cassette,
+    }) = STATE.get()
+    else {
+        return Ok(None);
+    };
+    let mut cassette = cassette
+        .lock()
+        .map_err(|_| anyhow!("Cassette lock poisoned"))?;
+    match cassette
+        .interactions
+        .iter_mut()
+        .find(|interaction| !interaction.used && interaction.request == *request)
+    {
+        Some(interaction) => {
+            interaction.used = true;
+            Ok(Some(interaction.events.clone()))
+        }
+        None => bail!(
+            "{} has no recorded response for this request",
+            path.display()
+        ),
+    }
+}
@@ -0,0 +1,155 @@
+use crate::core::{ask, open_stream, RequestExtra, Sampling};
+use crate::retry::RetryPolicy;
+use anyhow::{Context, Result};
+use openai::chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole};
+use openai::Credentials;
+use std::fs::{read_to_string, write};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// A conversation with a model, for programs that embed FerriteChatter instead of
+/// running its binaries. Nothing is printed; answers are returned, or handed to a
+/// callback piece by piece with `stream`.
+///
+/// ```ignore
+/// let mut chat = Chat::new("gpt-4o", Credentials::from_env()).system("Answer briefly.");
+/// let answer = chat.send("What is a ferrite?").await?;
+/// chat.stream("And a ferrite bead?", |delta| print!("{}", delta)).await?;
+/// chat.save(Path::new("conversation.json"))?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Chat {
+    model: String,
+    credentials: Credentials,
+    messages: Vec<ChatCompletionMessage>,
+    extra: RequestExtra,
+    sampling: Sampling,
+    policy: RetryPolicy,
+}
+
+impl Chat {
+    pub fn new(model: &str, credentials: Credentials) -> Self {
+        Self {
+            model: model.to_string(),
+            credentials,
+            messages: Vec::new(),
+            extra: RequestExtra::default(),
+            sampling: Sampling::default(),
+            policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Starts the conversation with a system prompt.
+    pub fn system(mut self, prompt: &str) -> Self {
+        self.messages
+            .insert(0, message(ChatCompletionMessageRole::System, prompt));
+        self
+    }
+
+    /// Continues an earlier conversation, e.g. one read with `load`.
+    pub fn with_messages(mut self, messages: Vec<ChatCompletionMessage>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    pub fn sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// User, metadata and headers added to every request, and the transport they go through.
+    pub fn extra(mut self, extra: RequestExtra) -> Self {
+        self.extra = extra;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Switches the model for the following requests.
+    pub fn set_model(&mut self, model: &str) {
+        self.model = model.to_string();
+    }
+
+    pub fn messages(&self) -> &[ChatCompletionMessage] {
+        &self.messages
+    }
+
+    /// Forgets everything but the system prompt.
+    pub fn reset(&mut self) {
+        self.messages
+            .retain(|message| message.role == ChatCompletionMessageRole::System);
+    }
+
+    /// Sends `prompt` and returns the answer, which is added to the conversation.
+    pub async fn send(&mut self, prompt: &str) -> Result<String> {
+        self.stream(prompt, |_| {}).await
+    }
+
+    /// Like `send`, but passes each piece of the answer to `on_delta` as it arrives.
+    /// When the request fails, the prompt isn't kept either.
+    pub async fn stream(&mut self, prompt: &str, on_delta: impl FnMut(&str)) -> Result<String> {
+        self.messages
+            .push(message(ChatCompletionMessageRole::User, prompt));
+        match self.answer(on_delta).await {
+            Ok(answer) => {
+                self.messages
+                    .push(message(ChatCompletionMessageRole::Assistant, &answer));
+                Ok(answer)
+            }
+            Err(e) => {
+                self.messages.pop();
+                Err(e)
+            }
+        }
+    }
+
+    async fn answer(&self, mut on_delta: impl FnMut(&str)) -> Result<String> {
+        let stream = open_stream(
+            ChatCompletionDelta::builder(&self.model, self.messages.clone()),
+            &self.credentials,
+            &self.extra,
+            &self.sampling,
+            &self.policy,
+        )
+        .await?;
+        ask(stream, |delta| {
+            on_delta(delta);
+            ControlFlow::Continue(())
+        })
+        .await?
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .with_context(|| "Can't get choices")
+    }
+
+    /// Writes the messages to `path` as JSON.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write(path, serde_json::to_string_pretty(&self.messages)?)
+            .with_context(|| format!("Can't write {}", path.display()))
+    }
+}
+
+/// Messages written by `Chat::save`.
+pub fn load(path: &Path) -> Result<Vec<ChatCompletionMessage>> {
+    let text = read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Can't parse {}", path.display()))
+}
+
+fn message(role: ChatCompletionMessageRole, content: &str) -> ChatCompletionMessage {
+    ChatCompletionMessage {
+        role,
+        content: Some(content.to_string()),
+        name: None,
+        function_call: None,
+        tool_call_id: None,
+        tool_calls: Vec::new(),
+    }
+}
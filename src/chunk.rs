@@ -0,0 +1,211 @@
+use std::ops::Range;
+
+/// Rough token count without a tokenizer: about four ASCII characters per token,
+/// and one token per character for everything else (CJK text, emoji, ...).
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    ascii.div_ceil(4) + other
+}
+
+/// A piece of the input together with the (1-based, inclusive) lines it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub text: String,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// Splits text into chunks of at most `max_tokens`, preferring to cut between
+/// paragraphs, before headings and around fenced code blocks. Consecutive chunks
+/// share up to `overlap_tokens` worth of trailing lines.
+#[derive(Debug, Clone, Copy)]
+pub struct Chunker {
+    pub max_tokens: usize,
+    pub overlap_tokens: usize,
+}
+
+impl Chunker {
+    pub fn new(max_tokens: usize, overlap_tokens: usize) -> Self {
+        Self {
+            max_tokens: max_tokens.max(1),
+            overlap_tokens: overlap_tokens.min(max_tokens / 2),
+        }
+    }
+
+    pub fn split(&self, text: &str) -> Vec<Chunk> {
+        let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+        let tokens = lines
+            .iter()
+            .map(|line| estimate_tokens(line))
+            .collect::<Vec<_>>();
+        let cost = |range: &Range<usize>| tokens[range.clone()].iter().sum::<usize>();
+
+        let mut chunks = Vec::new();
+        let mut current = 0..0;
+        for block in blocks(&lines) {
+            if cost(&current) + cost(&block) <= self.max_tokens {
+                current.end = block.end;
+                continue;
+            }
+            if !current.is_empty() {
+                chunks.push(current.clone());
+                current = self.overlap(&current, &tokens);
+                if cost(&current) + cost(&block) > self.max_tokens {
+                    current = block.start..block.start;
+                }
+            }
+            if cost(&block) <= self.max_tokens {
+                current = current.start..block.end;
+                continue;
+            }
+            // The block alone is too large: fall back to cutting between lines.
+            for line in block {
+                if !current.is_empty() && cost(&current) + tokens[line] > self.max_tokens {
+                    chunks.push(current.clone());
+                    current = self.overlap(&current, &tokens);
+                    if cost(&current) + tokens[line] > self.max_tokens {
+                        current = line..line;
+                    }
+                }
+                current.end = line + 1;
+            }
+        }
+        if !current.is_empty() {
+            chunks.push(current);
+        }
+
+        chunks
+            .into_iter()
+            .flat_map(|range| self.materialize(&lines, &tokens, range))
+            .collect()
+    }
+
+    /// Trailing lines of `range` that fit in the overlap budget.
+    fn overlap(&self, range: &Range<usize>, tokens: &[usize]) -> Range<usize> {
+        let mut start = range.end;
+        let mut total = 0;
+        while start > range.start + 1 && total + tokens[start - 1] <= self.overlap_tokens {
+            start -= 1;
+            total += tokens[start];
+        }
+        start..range.end
+    }
+
+    fn materialize(&self, lines: &[&str], tokens: &[usize], range: Range<usize>) -> Vec<Chunk> {
+        if range.len() == 1 && tokens[range.start] > self.max_tokens {
+            // A single line that can't fit anywhere is cut by characters.
+            return split_line(lines[range.start], self.max_tokens)
+                .into_iter()
+                .map(|text| Chunk {
+                    text,
+                    start_line: range.start + 1,
+                    end_line: range.start + 1,
+                })
+                .collect();
+        }
+        vec![Chunk {
+            text: lines[range.clone()].concat(),
+            start_line: range.start + 1,
+            end_line: range.end,
+        }]
+    }
+}
+
+/// A run of a Markdown document: prose, or a fenced code block including its fences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    Prose(String),
+    Code(String),
+}
+
+/// Splits a Markdown document into prose and fenced code blocks. Joined back together
+/// the parts are the original text; a fence that isn't closed runs to the end.
+pub fn parts(text: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_fence = false;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if fence && !in_fence && !current.is_empty() {
+            parts.push(Part::Prose(std::mem::take(&mut current)));
+        }
+        current.push_str(line);
+        if fence && in_fence {
+            parts.push(Part::Code(std::mem::take(&mut current)));
+        }
+        if fence {
+            in_fence = !in_fence;
+        }
+    }
+    if !current.is_empty() {
+        parts.push(match in_fence {
+            true => Part::Code(current),
+            false => Part::Prose(current),
+        });
+    }
+    parts
+}
+
+/// Groups lines into paragraphs, headings sections and fenced code blocks.
+fn blocks(lines: &[&str]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    let mut in_fence = false;
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if in_fence {
+            if fence {
+                in_fence = false;
+                blocks.push(start..i + 1);
+                start = i + 1;
+            }
+            continue;
+        }
+        if (fence || trimmed.starts_with('#')) && start < i {
+            blocks.push(start..i);
+            start = i;
+        }
+        if fence {
+            in_fence = true;
+        } else if trimmed.is_empty() {
+            blocks.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    if start < lines.len() {
+        blocks.push(start..lines.len());
+    }
+    blocks
+}
+
+fn split_line(line: &str, max_tokens: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let (mut ascii, mut other) = (0usize, 0usize);
+    for c in line.chars() {
+        piece.push(c);
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+        if ascii.div_ceil(4) + other >= max_tokens {
+            pieces.push(std::mem::take(&mut piece));
+            (ascii, other) = (0, 0);
+        }
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+    pieces
+}
@@ -0,0 +1,92 @@
+use crate::cassette;
+use crate::config::Config;
+use crate::core::{Model, DEFAULT_MODEL};
+use crate::transport::{self, ApiTransport};
+use crate::{log, metrics};
+use anyhow::{Context, Result};
+use openai::Credentials;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Options shared by every binary that talks to the API, flattened into its `Args`:
+///
+/// ```ignore
+/// #[derive(Parser)]
+/// struct Args {
+///     #[clap(flatten)]
+///     common: CommonArgs,
+///     prompt: Option<String>,
+/// }
+/// ```
+#[derive(clap::Args, Debug, Clone)]
+pub struct CommonArgs {
+    /// Print request details to stderr (-v: info, -vv: debug)
+    #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// Don't verify TLS certificates (for lab environments only)
+    #[clap(long = "insecure")]
+    pub insecure: bool,
+    /// Save the requests and streamed answers to a cassette file
+    #[clap(long = "record", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Answer requests from a cassette file instead of the API
+    #[clap(long = "replay")]
+    pub replay: Option<PathBuf>,
+    /// Provider profile defined in the config
+    #[clap(long = "profile")]
+    pub profile: Option<String>,
+    /// OpenAI API Key
+    #[clap(long = "key", short = 'k')]
+    pub key: Option<String>,
+    /// OpenAI API Base URL
+    #[clap(long = "base-url", short = 'b')]
+    pub base_url: Option<String>,
+    /// OpenAI Model
+    #[clap(long = "model", short = 'm', value_enum)]
+    pub model: Option<Model>,
+}
+
+/// Sets up logging, cassettes, the config and the transport, counts `command` in the
+/// metrics, and resolves the credentials and the model.
+///
+/// The API key comes from `--key`, then the keyring or the config, then `OPENAI_API_KEY`;
+/// the base URL from `--base-url`, then the config, then `OPENAI_BASE_URL`; the model
+/// from `--model`, then `default_model`.
+pub fn bootstrap(
+    args: &CommonArgs,
+    command: &str,
+) -> Result<(Credentials, Model, Config, Arc<dyn ApiTransport>)> {
+    log::init(args.verbose);
+    cassette::init(args.record.as_deref(), args.replay.as_deref())?;
+    let config = match &args.profile {
+        Some(profile) => Config::load()?.apply_profile(profile)?,
+        None => Config::load()?,
+    };
+    let transport = transport::configure(&config, args.insecure)?;
+    metrics::record_command(&config, command);
+
+    let key = match args.key.clone().or(config.get_openai_api_key().clone()) {
+        Some(key) => key,
+        None => env::var("OPENAI_API_KEY")
+            .with_context(|| "You need to set API key to the `OPENAI_API_KEY`")?,
+    };
+    let base_url = args
+        .base_url
+        .clone()
+        .or(config.get_openai_base_url().clone())
+        .unwrap_or_else(|| {
+            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string())
+        });
+    let model = args
+        .model
+        .clone()
+        .or(config.get_default_model().clone())
+        .unwrap_or(DEFAULT_MODEL);
+    Ok((
+        Credentials::new(key, base_url),
+        model,
+        config,
+        Arc::new(transport),
+    ))
+}
@@ -0,0 +1,241 @@
+use std::fmt;
+
+/// What a command does. fchat dispatches on it with an exhaustive match, so a command
+/// added here doesn't build until it's handled there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandKind {
+    Help,
+    Exit,
+    Reset,
+    Editor,
+    Save,
+    Undo,
+    Edit,
+    Retry,
+    Model,
+    Set,
+    Persona,
+    Bookmark,
+    Snippet,
+    Prompts,
+    Fetch,
+    Endpoint,
+    Compose,
+    Summarize,
+}
+
+/// A command of the fchat prompt. Its name and aliases resolve to `kind`.
+#[derive(Debug)]
+pub struct Command {
+    pub kind: CommandKind,
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// The arguments as shown in usage lines, empty when there are none.
+    pub arguments: &'static str,
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        kind: CommandKind::Help,
+        name: "/help",
+        aliases: &["/?"],
+        arguments: "",
+        help: "List the commands",
+    },
+    Command {
+        kind: CommandKind::Exit,
+        name: "exit",
+        aliases: &["/exit", "/quit"],
+        arguments: "",
+        help: "End the conversation",
+    },
+    Command {
+        kind: CommandKind::Reset,
+        name: "reset",
+        aliases: &["/reset"],
+        arguments: "",
+        help: "Start the conversation over",
+    },
+    Command {
+        kind: CommandKind::Editor,
+        name: "v",
+        aliases: &["/editor"],
+        arguments: "",
+        help: "Write the next message in the editor",
+    },
+    Command {
+        kind: CommandKind::Save,
+        name: "save",
+        aliases: &["/save"],
+        arguments: "",
+        help: "Save the conversation to a file",
+    },
+    Command {
+        kind: CommandKind::Undo,
+        name: "/undo",
+        aliases: &[],
+        arguments: "",
+        help: "Drop the last exchange",
+    },
+    Command {
+        kind: CommandKind::Edit,
+        name: "/edit",
+        aliases: &[],
+        arguments: "",
+        help: "Rewrite the last message in the editor and send it again",
+    },
+    Command {
+        kind: CommandKind::Retry,
+        name: "/retry",
+        aliases: &[],
+        arguments: "[-m <model>]",
+        help: "Regenerate the last answer, optionally with another model",
+    },
+    Command {
+        kind: CommandKind::Model,
+        name: "/model",
+        aliases: &[],
+        arguments: "[<model>]",
+        help: "Show the model or switch to another one",
+    },
+    Command {
+        kind: CommandKind::Set,
+        name: "/set",
+        aliases: &[],
+        arguments: "[<name> <value|none>]",
+        help: "Show or change temperature, top_p, max_tokens, reasoning_effort or n",
+    },
+    Command {
+        kind: CommandKind::Persona,
+        name: "/persona",
+        aliases: &[],
+        arguments: "<name>",
+        help: "Switch to a preset of the config",
+    },
+    Command {
+        kind: CommandKind::Bookmark,
+        name: "/bookmark",
+        aliases: &[],
+        arguments: "[<note>]",
+        help: "Bookmark the last answer",
+    },
+    Command {
+        kind: CommandKind::Snippet,
+        name: "/snippet",
+        aliases: &[],
+        arguments: "[<name>]",
+        help: "Send a saved snippet after editing it, or list the snippets",
+    },
+    Command {
+        kind: CommandKind::Prompts,
+        name: "/prompts",
+        aliases: &[],
+        arguments: "[<query>]",
+        help: "Pick a prompt from the imported catalogs",
+    },
+    Command {
+        kind: CommandKind::Fetch,
+        name: "/fetch",
+        aliases: &[],
+        arguments: "<url>",
+        help: "Add the text of a web page to the conversation",
+    },
+    Command {
+        kind: CommandKind::Endpoint,
+        name: "/endpoint",
+        aliases: &[],
+        arguments: "[<url>|reset]",
+        help: "Show or switch the API endpoint",
+    },
+    Command {
+        kind: CommandKind::Compose,
+        name: "/compose",
+        aliases: &[],
+        arguments: "",
+        help: "Toggle compose mode, where Enter adds a line",
+    },
+    Command {
+        kind: CommandKind::Summarize,
+        name: "/summarize",
+        aliases: &[],
+        arguments: "",
+        help: "Summarize the conversation so far",
+    },
+];
+
+impl fmt::Display for Command {
+    /// The usage line, e.g. `/retry [-m <model>]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.arguments {
+            "" => write!(f, "{}", self.name),
+            arguments => write!(f, "{} {}", self.name, arguments),
+        }
+    }
+}
+
+fn find(word: &str) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == word || command.aliases.contains(&word))
+}
+
+/// The command `input` invokes and the rest of the input. Commands without arguments
+/// only match on their own, so a message like "reset the router?" is still sent.
+pub fn parse(input: &str) -> Option<(&'static Command, &str)> {
+    let input = input.trim();
+    let (word, rest) = input
+        .split_once(char::is_whitespace)
+        .map_or((input, ""), |(word, rest)| (word, rest.trim()));
+    find(word)
+        .filter(|command| !command.arguments.is_empty() || rest.is_empty())
+        .map(|command| (command, rest))
+}
+
+/// What to tell the user when `input` starts like a `/command` but doesn't parse:
+/// the usage of a command that takes no arguments, or that there's no such command.
+/// Paths like `/etc/hosts` aren't taken for commands.
+pub fn mistake(input: &str) -> Option<String> {
+    let word = input.split_whitespace().next()?;
+    let name = word.strip_prefix('/')?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) || parse(input).is_some() {
+        return None;
+    }
+    Some(match find(word) {
+        Some(command) => format!("Usage: {}", command),
+        None => format!("Unknown command: {} (/help lists the commands)", word),
+    })
+}
+
+/// Names and aliases, for completion.
+pub fn names() -> Vec<&'static str> {
+    COMMANDS
+        .iter()
+        .flat_map(|command| std::iter::once(command.name).chain(command.aliases.iter().copied()))
+        .collect()
+}
+
+/// The usage lines and descriptions of every command, aligned.
+pub fn help() -> String {
+    let usages = COMMANDS
+        .iter()
+        .map(|command| command.to_string())
+        .collect::<Vec<_>>();
+    let width = usages
+        .iter()
+        .map(|usage| usage.len())
+        .max()
+        .unwrap_or_default();
+    COMMANDS
+        .iter()
+        .zip(usages)
+        .map(|(command, usage)| {
+            let aliases = match command.aliases {
+                [] => String::new(),
+                aliases => format!(" (also {})", aliases.join(", ")),
+            };
+            format!("{:<width$}  {}{}", usage, command.help, aliases)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
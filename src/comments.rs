@@ -0,0 +1,384 @@
+use std::path::Path;
+
+/// Comment and string syntax of a source language.
+#[derive(Debug, Clone, Copy)]
+pub struct Syntax {
+    pub line: &'static [&'static str],
+    pub block: Option<(&'static str, &'static str)>,
+    pub quotes: &'static [char],
+    /// `'x'` is a character literal rather than a string (and `'a` may be a lifetime).
+    pub char_literals: bool,
+    /// Rust raw strings, `r"…"` and `r#"…"#`.
+    pub raw_strings: bool,
+    /// Whether a quote opened on one line can close on a later one. Where it can't (shell,
+    /// YAML), an apostrophe as in `It's` doesn't hide the comments of the following lines.
+    pub multiline_strings: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line: &["//"],
+    block: Some(("/*", "*/")),
+    quotes: &['"'],
+    char_literals: true,
+    raw_strings: false,
+    multiline_strings: true,
+};
+
+const RUST: Syntax = Syntax {
+    raw_strings: true,
+    ..C_LIKE
+};
+
+const SCRIPT: Syntax = Syntax {
+    line: &["//"],
+    block: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    char_literals: false,
+    raw_strings: false,
+    multiline_strings: true,
+};
+
+const HASH: Syntax = Syntax {
+    line: &["#"],
+    block: None,
+    quotes: &['"', '\''],
+    char_literals: false,
+    raw_strings: false,
+    multiline_strings: false,
+};
+
+const DASH: Syntax = Syntax {
+    line: &["--"],
+    block: None,
+    quotes: &['"', '\''],
+    char_literals: false,
+    raw_strings: false,
+    multiline_strings: true,
+};
+
+const MARKUP: Syntax = Syntax {
+    line: &[],
+    block: Some(("<!--", "-->")),
+    quotes: &[],
+    char_literals: false,
+    raw_strings: false,
+    multiline_strings: true,
+};
+
+const SEMICOLON: Syntax = Syntax {
+    line: &[";"],
+    block: None,
+    quotes: &['"'],
+    char_literals: false,
+    raw_strings: false,
+    multiline_strings: true,
+};
+
+/// Call names whose string arguments are meant to be translated.
+const I18N_MARKERS: [&str; 6] = ["_", "__", "gettext", "tr", "t", "i18n"];
+
+impl Syntax {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name == "Makefile" || name == "Dockerfile" {
+            return Some(HASH);
+        }
+        match path.extension()?.to_str()? {
+            "rs" => Some(RUST),
+            "c" | "h" | "cc" | "cpp" | "hpp" | "cxx" | "java" | "go" | "cs" | "swift" | "kt"
+            | "kts" | "scala" | "dart" | "zig" => Some(C_LIKE),
+            "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "php" => Some(SCRIPT),
+            "css" | "scss" => Some(Syntax {
+                line: &[],
+                ..C_LIKE
+            }),
+            "py" | "rb" | "sh" | "bash" | "zsh" | "fish" | "pl" | "r" | "ex" | "exs" | "yaml"
+            | "yml" | "toml" | "nim" | "cmake" | "conf" => Some(HASH),
+            "sql" | "lua" | "hs" => Some(DASH),
+            "html" | "htm" | "xml" | "svg" | "vue" => Some(MARKUP),
+            "lisp" | "el" | "clj" | "scm" | "ini" | "asm" => Some(SEMICOLON),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Comment,
+    /// Contents of a string literal passed to an i18n call, delimited by the given quote.
+    String(char),
+}
+
+/// Byte range of translatable text inside the source. Comment markers (including doc
+/// markers like `///` and `//!`), leading `*` of block comment lines and surrounding
+/// whitespace are never part of a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    pub kind: SegmentKind,
+}
+
+pub fn extract(source: &str, syntax: &Syntax) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let bytes = source.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let rest = &source[i..];
+        if i == 0 && rest.starts_with("#!") {
+            // A shebang line is an interpreter path, not a comment.
+            i = rest.find('\n').unwrap_or(source.len());
+        } else if let Some(marker) = syntax.line.iter().find(|m| rest.starts_with(**m)) {
+            let start = i + marker_len(rest, marker);
+            let end = rest.find('\n').map(|n| i + n).unwrap_or(source.len());
+            push_trimmed(&mut segments, source, start, end, SegmentKind::Comment);
+            i = end;
+        } else if let Some((open, close)) = syntax.block.filter(|(open, _)| rest.starts_with(open))
+        {
+            let start = i + open.len();
+            let end = source[start..]
+                .find(close)
+                .map(|n| start + n)
+                .unwrap_or(source.len());
+            let mut line_start = start;
+            for line in source[start..end].split_inclusive('\n') {
+                let line_end = line_start + line.trim_end_matches('\n').len();
+                let text = &source[line_start..line_end];
+                let stripped = text.trim_start();
+                let offset = match stripped.strip_prefix('*') {
+                    Some(after) if !after.starts_with('/') => text.len() - after.len(),
+                    _ => text.len() - stripped.len(),
+                };
+                push_trimmed(
+                    &mut segments,
+                    source,
+                    line_start + offset,
+                    line_end,
+                    SegmentKind::Comment,
+                );
+                line_start += line.len();
+            }
+            i = (end + close.len()).min(source.len());
+        } else if let Some(len) = syntax
+            .raw_strings
+            .then(|| raw_string_len(source, i))
+            .flatten()
+        {
+            i += len;
+        } else if syntax.char_literals && rest.starts_with('\'') {
+            i += char_literal_len(rest).unwrap_or(1);
+        } else if let Some(quote) = rest.chars().next().filter(|c| syntax.quotes.contains(c)) {
+            let start = i + quote.len_utf8();
+            let end = string_end(source, start, quote, syntax.multiline_strings);
+            if is_i18n_call(&source[..i]) {
+                segments.push(Segment {
+                    start,
+                    end,
+                    kind: SegmentKind::String(quote),
+                });
+            }
+            i = (end + quote.len_utf8()).min(source.len());
+        } else {
+            i += rest.chars().next().map(char::len_utf8).unwrap_or(1);
+        }
+    }
+
+    segments
+}
+
+/// Replaces every segment with its translation, leaving everything else byte-for-byte intact.
+pub fn replace(source: &str, segments: &[Segment], translations: &[String]) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut last = 0;
+    for (segment, translation) in segments.iter().zip(translations) {
+        out.push_str(&source[last..segment.start]);
+        match segment.kind {
+            SegmentKind::Comment => out.push_str(&translation.replace('\n', " ")),
+            SegmentKind::String(quote) => out.push_str(&escape(translation, quote)),
+        }
+        last = segment.end;
+    }
+    out.push_str(&source[last..]);
+    out
+}
+
+fn push_trimmed(
+    segments: &mut Vec<Segment>,
+    source: &str,
+    start: usize,
+    end: usize,
+    kind: SegmentKind,
+) {
+    let text = &source[start..end];
+    let trimmed = text.trim();
+    if trimmed.chars().any(char::is_alphabetic) {
+        let start = start + (text.len() - text.trim_start().len());
+        segments.push(Segment {
+            start,
+            end: start + trimmed.len(),
+            kind,
+        });
+    }
+}
+
+/// Where the string opened before `start` closes; without `multiline`, an unclosed one
+/// ends with its line.
+fn string_end(source: &str, start: usize, quote: char, multiline: bool) -> usize {
+    let mut escaped = false;
+    for (offset, c) in source[start..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\n' if !multiline => return start + offset,
+            '\\' => escaped = true,
+            c if c == quote => return start + offset,
+            _ => {}
+        }
+    }
+    source.len()
+}
+
+/// Length of the line comment marker `marker` at the start of `rest`, with the doc or
+/// heading forms that repeat its last character (`///`, `##`, `;;;`) and a trailing `!`
+/// (`//!`, `#!`).
+fn marker_len(rest: &str, marker: &str) -> usize {
+    let Some(last) = marker.chars().next_back() else {
+        return 0;
+    };
+    let after = rest[marker.len()..].trim_start_matches(last);
+    let after = after.strip_prefix('!').unwrap_or(after);
+    rest.len() - after.len()
+}
+
+/// Length of a Rust raw string (`r"…"`, `r#"…"#`, also with a `b` or `c` prefix) starting
+/// at `i`, which must not continue an identifier.
+fn raw_string_len(source: &str, i: usize) -> Option<usize> {
+    let rest = &source[i..];
+    let prefix = ["br", "cr", "r"]
+        .into_iter()
+        .find(|prefix| rest.starts_with(prefix))?;
+    if source[..i]
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_')
+    {
+        return None;
+    }
+    let after = &rest[prefix.len()..];
+    let hashes = after.len() - after.trim_start_matches('#').len();
+    if !after[hashes..].starts_with('"') {
+        return None;
+    }
+    let body = prefix.len() + hashes + 1;
+    let close = format!("\"{}", "#".repeat(hashes));
+    Some(
+        rest[body..]
+            .find(&close)
+            .map_or(rest.len(), |end| body + end + close.len()),
+    )
+}
+
+fn char_literal_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    let (_, c) = chars.next()?;
+    if c == '\\' {
+        // Escape sequences such as '\n' or '\u{1F600}'.
+        return rest[1..]
+            .char_indices()
+            .take(12)
+            .skip(2)
+            .find(|(_, c)| *c == '\'')
+            .map(|(offset, _)| offset + 2);
+    }
+    // `'a'` is a literal, `'a` alone is a lifetime or label.
+    chars
+        .next()
+        .filter(|(_, c)| *c == '\'')
+        .map(|(offset, _)| offset + 1)
+}
+
+fn is_i18n_call(before: &str) -> bool {
+    let before = before.trim_end();
+    let Some(call) = before.strip_suffix('(') else {
+        return false;
+    };
+    let call = call.trim_end().trim_end_matches('!');
+    I18N_MARKERS.iter().any(|marker| {
+        call.strip_suffix(marker).is_some_and(|prefix| {
+            !prefix
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+        })
+    })
+}
+
+/// Escapes bare quotes and newlines the model introduced. Existing escape
+/// sequences are sent and received verbatim, so backslashes are left alone.
+fn escape(text: &str, quote: char) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut backslash = false;
+    for c in text.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            c if c == quote && !backslash => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+        backslash = c == '\\' && !backslash;
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts<'a>(source: &'a str, syntax: &Syntax) -> Vec<&'a str> {
+        extract(source, syntax)
+            .iter()
+            .map(|segment| &source[segment.start..segment.end])
+            .collect()
+    }
+
+    #[test]
+    fn doc_markers_stay_out_of_segments() {
+        let source = "/// Adds one.\n//! The crate.\n// Plain.\nfn f() {}\n";
+        assert_eq!(texts(source, &RUST), ["Adds one.", "The crate.", "Plain."]);
+        let translated = replace(
+            source,
+            &extract(source, &RUST),
+            &["1を足す。".into(), "クレート。".into(), "普通。".into()],
+        );
+        assert_eq!(
+            translated,
+            "/// 1を足す。\n//! クレート。\n// 普通。\nfn f() {}\n"
+        );
+    }
+
+    #[test]
+    fn raw_strings_are_skipped() {
+        let source = "let s = r#\"a \" // x\"#; // real\nlet t = br\"// y\";\n";
+        assert_eq!(texts(source, &RUST), ["real"]);
+    }
+
+    #[test]
+    fn identifiers_ending_in_r_are_not_raw_strings() {
+        let source = "let s = for_r\"// not a comment\"; // real\n";
+        assert_eq!(texts(source, &RUST), ["real"]);
+    }
+
+    #[test]
+    fn apostrophes_dont_hide_hash_comments() {
+        let source = "message: It's fine\n# Set the name.\nname: x\n";
+        assert_eq!(texts(source, &HASH), ["Set the name."]);
+    }
+
+    #[test]
+    fn shebang_is_not_a_comment() {
+        let source = "#!/usr/bin/env bash\n## Build it.\nmake\n";
+        assert_eq!(texts(source, &HASH), ["Build it."]);
+    }
+}
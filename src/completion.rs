@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::Path;
+
+/// What Tab completes at the fchat prompt: commands at the start of the input, their
+/// arguments after them, and file paths of `@path` references anywhere.
+#[derive(Debug, Default, Clone)]
+pub struct Completer {
+    commands: Vec<String>,
+    arguments: HashMap<String, Vec<String>>,
+}
+
+impl Completer {
+    pub fn new(commands: &[&str]) -> Self {
+        Self {
+            commands: commands.iter().map(|command| command.to_string()).collect(),
+            arguments: HashMap::new(),
+        }
+    }
+
+    /// Values completed after `command`, e.g. snippet names after `/snippet`.
+    pub fn argument(mut self, command: &str, values: Vec<String>) -> Self {
+        self.arguments.insert(command.to_string(), values);
+        self
+    }
+
+    /// Where the word being completed starts in `line` (the text before the cursor)
+    /// and the words it can become, sorted.
+    pub fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| {
+            i + line[i..].chars().next().map_or(1, char::len_utf8)
+        });
+        let word = &line[start..];
+        let mut candidates = if let Some(path) = word.strip_prefix('@') {
+            paths(path)
+                .into_iter()
+                .map(|path| format!("@{}", path))
+                .collect()
+        } else if start == 0 {
+            self.commands
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .cloned()
+                .collect()
+        } else {
+            // Only the first argument of a command is completed.
+            match line[..start].split_whitespace().collect::<Vec<_>>()[..] {
+                [command] => self
+                    .arguments
+                    .get(command)
+                    .into_iter()
+                    .flatten()
+                    .filter(|value| value.starts_with(word))
+                    .cloned()
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+}
+
+/// Entries of the directory of `prefix` that start with its file name part.
+/// Directories end with `/`; hidden files are offered only for a prefix starting with `.`.
+fn paths(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+    let Ok(entries) = read_dir(Path::new(if dir.is_empty() { "." } else { dir })) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file_name, slash))
+        })
+        .collect()
+}
+
+/// The longest text every candidate starts with.
+pub fn common_prefix(candidates: &[String]) -> &str {
+    let Some(first) = candidates.first() else {
+        return "";
+    };
+    let mut end = first.len();
+    for candidate in &candidates[1..] {
+        end = first[..end]
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(end.min(candidate.len()), |((i, _), _)| i);
+    }
+    &first[..end]
+}
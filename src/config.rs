@@ -1,17 +1,91 @@
 use crate::core;
-use anyhow::{Context, Result};
+use crate::keyring;
+use crate::retry::RetryPolicy;
+use crate::sink::Sink;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::{self, Deserializer, Visitor};
 use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::env;
 use std::fs::read_to_string;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tia::Tia;
 
+pub type Presets = HashMap<String, String>;
+/// Sinks each answer is sent to, by persona.
+pub type Outputs = HashMap<String, Vec<Sink>>;
+pub type Profiles = HashMap<String, Profile>;
+pub type ModelParams = HashMap<String, core::Sampling>;
+/// USD per million input tokens, by model.
+pub type Prices = HashMap<String, f64>;
+
 #[derive(Debug, Tia, Deserialize)]
 #[tia(rg)]
 pub struct Config {
     openai_api_key: Option<String>,
     openai_base_url: Option<String>,
     default_model: Option<core::Model>,
+    presets: Option<Presets>,
+    metrics: Option<bool>,
+    system_prompt: Option<String>,
+    user: Option<String>,
+    metadata: Option<core::Metadata>,
+    terminal_integration: Option<bool>,
+    headers: Option<core::Headers>,
+    profiles: Option<Profiles>,
+    no_train: Option<bool>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u64>,
+    reasoning_effort: Option<core::ReasoningEffort>,
+    model_params: Option<ModelParams>,
+    retry: Option<RetrySettings>,
+    fence_code: Option<bool>,
+    large_input_bytes: Option<usize>,
+    input_prices: Option<Prices>,
+    outputs: Option<Outputs>,
+    proxy: Option<String>,
+    ca_bundle: Option<String>,
+    warmup: Option<bool>,
+    history: Option<bool>,
+    translate_to: Option<String>,
+    glossary: Option<String>,
+    compact_tokens: Option<usize>,
+    summary_language: Option<String>,
+    trusted_projects: Option<Vec<PathBuf>>,
+}
+
+/// Retry thresholds. Delays are in seconds.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetrySettings {
+    max_attempts: Option<u32>,
+    base_delay: Option<f64>,
+    max_delay: Option<f64>,
+}
+
+/// Named provider settings selected with `--profile`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    openai_api_key: Option<String>,
+    openai_base_url: Option<String>,
+    default_model: Option<core::Model>,
+    headers: Option<core::Headers>,
+}
+
+/// Per-directory overrides read from the nearest `.ferrite.yaml`.
+/// Only settings that describe the project are read here; others are warned about. The API key comes from
+/// the global config, so `openai_base_url` is only honoured for directories listed in
+/// `trusted_projects`; otherwise any cloned repository could send the key elsewhere.
+#[derive(Debug, Default, Deserialize)]
+pub struct ProjectConfig {
+    openai_base_url: Option<String>,
+    default_model: Option<core::Model>,
+    system_prompt: Option<String>,
 }
 
 impl Default for Config {
@@ -20,25 +94,446 @@ impl Default for Config {
             openai_api_key: None,
             openai_base_url: None,
             default_model: Some(crate::core::Model::Gpt_4o),
+            presets: None,
+            metrics: None,
+            system_prompt: None,
+            user: None,
+            metadata: None,
+            terminal_integration: None,
+            headers: None,
+            profiles: None,
+            no_train: None,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            model_params: None,
+            retry: None,
+            fence_code: None,
+            large_input_bytes: None,
+            input_prices: None,
+            outputs: None,
+            proxy: None,
+            ca_bundle: None,
+            warmup: None,
+            history: None,
+            translate_to: None,
+            glossary: None,
+            compact_tokens: None,
+            summary_language: None,
+            trusted_projects: None,
+        }
+    }
+}
+
+/// Top-level settings of the config file: the fields of `Config`, as serde reports them.
+pub fn keys() -> &'static [&'static str] {
+    field_names::<Config>()
+}
+
+/// The fields of the struct `T`, as serde reports them.
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let fields = Cell::new(&[][..]);
+    let _ = T::deserialize(FieldNames(&fields));
+    fields.get()
+}
+
+/// A deserializer that only takes note of the field names of the struct asked for.
+struct FieldNames<'a>(&'a Cell<&'static [&'static str]>);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom(
+            "only the field names of a struct are read",
+        ))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.set(fields);
+        self.deserialize_any(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+pub fn config_dir() -> Result<PathBuf> {
+    Ok(PathBuf::from(env::var("XDG_CONFIG_HOME").unwrap_or(
+        format!(
+            "{}/.config",
+            env::var("HOME").with_context(|| "Where is the HOME?")?
+        ),
+    )))
+}
+
+/// Directory for files FerriteChatter manages itself (metrics, history, ...).
+pub fn data_dir() -> Result<PathBuf> {
+    Ok(config_dir()?.join("ferrite"))
+}
+
+/// Walks up from the current directory looking for `.ferrite.yaml`.
+pub fn find_project_config() -> Option<PathBuf> {
+    let mut dir = env::current_dir().ok()?;
+    loop {
+        let candidate = dir.join(".ferrite.yaml");
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+impl ProjectConfig {
+    /// Loads a project config, warning about settings it doesn't know like `Config::load`,
+    /// so a project file written for a newer version doesn't stop every command.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = read_to_string(path)
+            .with_context(|| format!("Can't read project config {}", path.display()))?;
+        let known = field_names::<Self>();
+        for key in unknown_keys(&text, known)? {
+            eprintln!("Warning: {}: {}", path.display(), unknown(&key, known));
         }
+        serde_yaml::from_str(&text)
+            .with_context(|| format!("Can't parse project config {}", path.display()))
+    }
+
+    /// Checks project config text like `Config::validate`.
+    pub fn validate(text: &str) -> Result<()> {
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+        check_keys(text, field_names::<Self>())?;
+        serde_yaml::from_str::<Self>(text).with_context(|| "Invalid project config")?;
+        Ok(())
     }
 }
 
 impl Config {
+    /// Loads the global config and applies the nearest project config on top of it.
+    /// Precedence: command line > project `.ferrite.yaml` > global config > environment,
+    /// where the API key in the OS keyring takes the place of the global config's.
     pub fn load() -> Result<Self> {
-        let path = format!(
-            "{}/.ferriteconf.yaml",
-            env::var("XDG_CONFIG_HOME").unwrap_or(format!(
-                "{}/.config",
-                env::var("HOME").with_context(|| "Where is the HOME?")?
-            ))
-        );
-
-        if !Path::new(&path).exists() {
-            Ok(Self::default())
+        let path = Self::path()?;
+
+        let mut config: Self = if !path.exists() {
+            Self::default()
         } else {
-            serde_yaml::from_str(&read_to_string(path).with_context(|| "Can't read config file")?)
-                .with_context(|| "Can't parse config file")
+            let text = read_to_string(&path).with_context(|| "Can't read config file")?;
+            // Stale or misspelled settings shouldn't stop every command after an upgrade;
+            // `ferrite config check` reports them as errors.
+            for key in unknown_keys(&text, keys())? {
+                eprintln!("Warning: {}: {}", path.display(), unknown(&key, keys()));
+            }
+            serde_yaml::from_str(&text).with_context(|| "Can't parse config file")?
+        };
+        // A key stored with `ferrite auth login` wins over the plaintext one.
+        if let Some(key) = keyring::get() {
+            config.openai_api_key = Some(key);
+        }
+
+        match find_project_config() {
+            Some(path) => {
+                let mut project = ProjectConfig::load(&path)?;
+                if project.openai_base_url.is_some() && !config.trusts(&path) {
+                    eprintln!(
+                        "Ignoring openai_base_url of {}: add its directory to `trusted_projects` in {} to use it",
+                        path.display(),
+                        Self::path()?.display()
+                    );
+                    project.openai_base_url = None;
+                }
+                Ok(config.merge(project))
+            }
+            None => Ok(config),
+        }
+    }
+
+    pub fn path() -> Result<PathBuf> {
+        Ok(config_dir()?.join(".ferriteconf.yaml"))
+    }
+
+    /// Checks that config text parses and has no unknown settings, suggesting the
+    /// closest known name for typos like `defaut_model`.
+    pub fn validate(text: &str) -> Result<()> {
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+        check_keys(text, keys())?;
+        serde_yaml::from_str::<Self>(text).with_context(|| "Invalid config")?;
+        Ok(())
+    }
+
+    /// Whether the directory of the project config at `path` is in `trusted_projects`.
+    fn trusts(&self, path: &Path) -> bool {
+        let Some(dir) = path.parent().and_then(|dir| dir.canonicalize().ok()) else {
+            return false;
+        };
+        self.trusted_projects
+            .iter()
+            .flatten()
+            .filter_map(|trusted| trusted.canonicalize().ok())
+            .any(|trusted| trusted == dir)
+    }
+
+    pub fn merge(self, project: ProjectConfig) -> Self {
+        Self {
+            openai_base_url: project.openai_base_url.or(self.openai_base_url),
+            default_model: project.default_model.or(self.default_model),
+            system_prompt: project.system_prompt.or(self.system_prompt),
+            ..self
+        }
+    }
+
+    /// Overlays the named profile on top of this config.
+    pub fn apply_profile(self, name: &str) -> Result<Self> {
+        let profile = self
+            .profiles
+            .as_ref()
+            .and_then(|profiles| profiles.get(name))
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown profile: {}", name))?;
+        let mut headers = self.headers.clone().unwrap_or_default();
+        headers.extend(profile.headers.unwrap_or_default());
+        Ok(Self {
+            openai_api_key: profile.openai_api_key.or(self.openai_api_key),
+            openai_base_url: profile.openai_base_url.or(self.openai_base_url),
+            default_model: profile.default_model.or(self.default_model),
+            headers: Some(headers),
+            ..self
+        })
+    }
+
+    /// Sampling defaults for `model`: its `model_params` entry, then the global settings.
+    pub fn sampling(&self, model: &str) -> core::Sampling {
+        let global = core::Sampling {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            reasoning_effort: self.reasoning_effort,
+            n: None,
+        };
+        self.model_params
+            .as_ref()
+            .and_then(|params| params.get(model))
+            .cloned()
+            .unwrap_or_default()
+            .or(global)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let defaults = RetryPolicy::default();
+        let settings = self.retry.clone().unwrap_or_default();
+        let seconds = |s: Option<f64>, default: Duration| {
+            s.and_then(|s| Duration::try_from_secs_f64(s).ok())
+                .unwrap_or(default)
+        };
+        RetryPolicy {
+            max_attempts: settings
+                .max_attempts
+                .unwrap_or(defaults.max_attempts)
+                .max(1),
+            base_delay: seconds(settings.base_delay, defaults.base_delay),
+            max_delay: seconds(settings.max_delay, defaults.max_delay),
+        }
+    }
+
+    pub fn no_train_enabled(&self) -> bool {
+        self.no_train.unwrap_or(false)
+    }
+
+    pub fn fence_code_enabled(&self) -> bool {
+        self.fence_code.unwrap_or(true)
+    }
+
+    /// Piped input above this size needs confirmation (200KB unless configured).
+    pub fn large_input_limit(&self) -> usize {
+        self.large_input_bytes.unwrap_or(200 * 1024)
+    }
+
+    pub fn input_price(&self, model: &str) -> Option<f64> {
+        self.input_prices
+            .as_ref()
+            .and_then(|prices| prices.get(model))
+            .copied()
+    }
+
+    pub fn metrics_enabled(&self) -> bool {
+        self.metrics.unwrap_or(false)
+    }
+
+    pub fn warmup_enabled(&self) -> bool {
+        self.warmup.unwrap_or(false)
+    }
+
+    pub fn history_enabled(&self) -> bool {
+        self.history.unwrap_or(true)
+    }
+
+    pub fn terminal_integration_enabled(&self) -> bool {
+        self.terminal_integration.unwrap_or(false)
+    }
+
+    pub fn preset(&self, name: &str) -> Result<String> {
+        self.presets
+            .as_ref()
+            .and_then(|presets| presets.get(name))
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown preset: {}", name))
+    }
+
+    pub fn outputs(&self, persona: Option<&str>) -> &[Sink] {
+        persona
+            .and_then(|persona| self.outputs.as_ref()?.get(persona))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Top-level keys of config text that aren't among the `known` settings.
+pub fn unknown_keys(text: &str, known: &[&str]) -> Result<Vec<String>> {
+    Ok(document(text)?
+        .as_mapping()
+        .into_iter()
+        .flat_map(|mapping| mapping.keys())
+        .filter_map(|key| key.as_str())
+        .filter(|key| !known.contains(key))
+        .map(String::from)
+        .collect())
+}
+
+/// Fails with every top-level key of config text that isn't among the `known` settings.
+fn check_keys(text: &str, known: &[&str]) -> Result<()> {
+    let unknown = unknown_keys(text, known)?
+        .iter()
+        .map(|key| unknown(key, known))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        bail!("{}", unknown.join("\n"));
+    }
+    Ok(())
+}
+
+/// The value at `key` (dotted for nested maps, e.g. `presets.reviewer`).
+pub fn get_value(text: &str, key: &str) -> Result<Option<Value>> {
+    let mut value = document(text)?;
+    for part in key.split('.') {
+        match value.get(part) {
+            Some(inner) => value = inner.clone(),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/// Sets `key` (dotted for nested maps) to `value`, given as YAML, and returns the new
+/// text once it parses. A top-level one-line setting is rewritten in place so the
+/// comments and layout of the file are kept; anything else re-serializes the document.
+pub fn set_value(text: &str, key: &str, value: &str) -> Result<String> {
+    let top = key.split('.').next().unwrap_or_default();
+    if !keys().contains(&top) {
+        bail!("Unknown setting: {}", top);
+    }
+    let value: Value =
+        serde_yaml::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+
+    let inline = !key.contains('.') && !value.is_mapping() && !value.is_sequence();
+    let updated = match in_place(text, key, &value).filter(|_| inline) {
+        Some(updated) => updated,
+        None => {
+            let mut document = document(text)?;
+            let mut target = &mut document;
+            for part in key.split('.') {
+                if !target.is_mapping() {
+                    *target = Value::Mapping(Mapping::new());
+                }
+                let Value::Mapping(mapping) = target else {
+                    unreachable!()
+                };
+                target = mapping
+                    .entry(Value::String(part.to_string()))
+                    .or_insert(Value::Null);
+            }
+            *target = value;
+            serde_yaml::to_string(&document)?
+        }
+    };
+    // Only what this change breaks; settings the file already didn't know are left to
+    // `ferrite config check`.
+    serde_yaml::from_str::<Config>(&updated).with_context(|| "Invalid config")?;
+    Ok(updated)
+}
+
+/// The message for an unknown setting, with the closest `known` one if it looks like a typo.
+fn unknown(key: &str, known: &[&str]) -> String {
+    match suggest(key, known) {
+        Some(known) => format!("unknown setting `{}`, did you mean `{}`?", key, known),
+        None => format!("unknown setting `{}`", key),
+    }
+}
+
+/// The `known` setting closest to a misspelled one.
+pub fn suggest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|known| (distance(key, known), *known))
+        .filter(|(distance, known)| *distance <= (known.len() / 3).max(1))
+        .min()
+        .map(|(_, known)| known)
+}
+
+/// Levenshtein distance.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn document(text: &str) -> Result<Value> {
+    if text.trim().is_empty() {
+        return Ok(Value::Mapping(Mapping::new()));
+    }
+    serde_yaml::from_str(text).with_context(|| "Can't parse config file")
+}
+
+/// Replaces the line of a top-level one-line setting, or appends one when it's missing.
+/// `None` when the setting spans several lines.
+fn in_place(text: &str, key: &str, value: &Value) -> Option<String> {
+    let rendered = serde_yaml::to_string(value).ok()?;
+    let line = format!("{}: {}", key, rendered.trim_end());
+    let mut lines = text.lines().map(String::from).collect::<Vec<_>>();
+    let prefix = format!("{}:", key);
+    match lines.iter().position(|l| l.starts_with(&prefix)) {
+        Some(index) => {
+            let rest = lines[index][prefix.len()..].trim();
+            let block = rest.is_empty() || rest.starts_with('|') || rest.starts_with('>');
+            let continued = lines
+                .get(index + 1)
+                .is_some_and(|next| next.starts_with([' ', '\t', '-']));
+            if block || continued {
+                return None;
+            }
+            lines[index] = line;
         }
+        None => lines.push(line),
     }
+    Some(lines.join("\n") + "\n")
 }
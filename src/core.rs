@@ -1,35 +1,660 @@
-use anyhow::{anyhow, Result};
+use crate::cassette;
+use crate::chunk::estimate_tokens;
+use crate::retry::{self, RetryPolicy};
+use crate::spinner::Spinner;
+use crate::transport::{ApiTransport, Events, Reply, ReqwestTransport};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::ValueEnum;
 use ferrite_model_gen::generate_models;
-use openai::chat::{ChatCompletion, ChatCompletionDelta};
+use futures_util::StreamExt;
+use openai::{
+    chat::{ChatCompletion, ChatCompletionBuilder, ChatCompletionDelta},
+    Credentials,
+};
 use serde::de::{self, Deserializer, Visitor};
 use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fmt;
-use std::io::{stdout, Write};
-use tokio::sync::mpsc::Receiver;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tracing::{debug, info, warn};
 
 generate_models!();
 pub const DEFAULT_MODEL: Model = Model::Gpt_4o;
 
-pub async fn ask(mut stream: Receiver<ChatCompletionDelta>) -> Result<ChatCompletion> {
+pub type Metadata = BTreeMap<String, String>;
+pub type Headers = BTreeMap<String, String>;
+
+/// Top-level request fields and HTTP headers that `ChatCompletionBuilder` doesn't expose,
+/// and the transport the request is sent through.
+#[derive(Debug, Clone)]
+pub struct RequestExtra {
+    body: Map<String, Value>,
+    headers: Headers,
+    transport: Arc<dyn ApiTransport>,
+}
+
+impl Default for RequestExtra {
+    fn default() -> Self {
+        Self {
+            body: Map::new(),
+            headers: Headers::new(),
+            transport: Arc::new(ReqwestTransport::default()),
+        }
+    }
+}
+
+impl RequestExtra {
+    pub fn user(mut self, user: Option<String>) -> Self {
+        if let Some(user) = user {
+            self.body.insert(String::from("user"), Value::String(user));
+        }
+        self
+    }
+
+    /// Asks the provider not to retain the request or use it for training.
+    /// OpenAI understands `store: false`; OpenRouter wants `provider.data_collection`.
+    /// Calling it again for another endpoint replaces the previous setting.
+    pub fn no_train(mut self, enabled: bool, base_url: &str) -> Self {
+        if enabled {
+            self.body.remove("store");
+            self.body.remove("provider");
+            if base_url.contains("openrouter.ai") {
+                self.body.insert(
+                    String::from("provider"),
+                    serde_json::json!({ "data_collection": "deny" }),
+                );
+            } else {
+                self.body.insert(String::from("store"), Value::Bool(false));
+            }
+        }
+        self
+    }
+
+    /// Sends the requests through `transport` instead of a default `ReqwestTransport`.
+    pub fn transport(mut self, transport: Arc<dyn ApiTransport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn headers(mut self, headers: Headers) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        if !metadata.is_empty() {
+            self.body.insert(
+                String::from("metadata"),
+                Value::Object(
+                    metadata
+                        .into_iter()
+                        .map(|(k, v)| (k, Value::String(v)))
+                        .collect(),
+                ),
+            );
+        }
+        self
+    }
+}
+
+/// What a model family accepts in a chat completion request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Takes `system` messages. Models that don't get them as `user` messages instead.
+    pub system_role: bool,
+    /// Takes `temperature` and `top_p`.
+    pub sampling: bool,
+    /// Takes `reasoning_effort` and counts its limit in `max_completion_tokens`.
+    pub reasoning: bool,
+}
+
+const CHAT: Capabilities = Capabilities {
+    system_role: true,
+    sampling: true,
+    reasoning: false,
+};
+
+const EARLY_REASONING: Capabilities = Capabilities {
+    system_role: false,
+    sampling: false,
+    reasoning: false,
+};
+
+const REASONING: Capabilities = Capabilities {
+    system_role: false,
+    sampling: false,
+    reasoning: true,
+};
+
+/// Model name prefixes and their capabilities. The first matching entry wins.
+const CAPABILITIES: [(&str, Capabilities); 5] = [
+    ("o1-mini", EARLY_REASONING),
+    ("o1-preview", EARLY_REASONING),
+    ("o1", REASONING),
+    ("o3", REASONING),
+    ("o4", REASONING),
+];
+
+/// Capabilities of `model`. Provider prefixes such as `openai/` are ignored.
+pub fn capabilities(model: &str) -> Capabilities {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    CAPABILITIES
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, capabilities)| *capabilities)
+        .unwrap_or(CHAT)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+}
+
+/// Sampling parameters shared by every request of a conversation.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sampling {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u64>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Number of answers to generate; more than one lets the user pick.
+    pub n: Option<u8>,
+}
+
+impl Sampling {
+    /// Fills the parameters that aren't set here from `fallback`.
+    pub fn or(self, fallback: Sampling) -> Sampling {
+        Sampling {
+            temperature: self.temperature.or(fallback.temperature),
+            top_p: self.top_p.or(fallback.top_p),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            reasoning_effort: self.reasoning_effort.or(fallback.reasoning_effort),
+            n: self.n.or(fallback.n),
+        }
+    }
+
+    pub fn apply(&self, mut builder: ChatCompletionBuilder) -> ChatCompletionBuilder {
+        if let Some(temperature) = self.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            builder = builder.top_p(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        if let Some(n) = self.n {
+            builder = builder.n(n);
+        }
+        builder
+    }
+
+    /// Whether more than one answer is requested.
+    pub fn multiple(&self) -> bool {
+        self.n.is_some_and(|n| n > 1)
+    }
+
+    /// Updates a parameter by name. `none` clears it so the API default applies.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let clear = matches!(value, "none" | "default");
+        match key {
+            "temperature" => self.temperature = if clear { None } else { Some(value.parse()?) },
+            "top_p" | "top-p" => self.top_p = if clear { None } else { Some(value.parse()?) },
+            "max_tokens" | "max-tokens" => {
+                self.max_tokens = if clear { None } else { Some(value.parse()?) }
+            }
+            "reasoning_effort" | "reasoning-effort" => {
+                self.reasoning_effort = if clear {
+                    None
+                } else {
+                    Some(ReasoningEffort::from_str(value, true).map_err(|e| anyhow!(e))?)
+                }
+            }
+            "n" => self.n = if clear { None } else { Some(value.parse()?) },
+            _ => bail!("Unknown parameter: {}", key),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |v: Option<String>| v.unwrap_or_else(|| String::from("default"));
+        write!(
+            f,
+            "temperature={} top_p={} max_tokens={} reasoning_effort={} n={}",
+            show(self.temperature.map(|v| v.to_string())),
+            show(self.top_p.map(|v| v.to_string())),
+            show(self.max_tokens.map(|v| v.to_string())),
+            show(self.reasoning_effort.map(|v| v.as_str().to_string())),
+            show(self.n.map(|v| v.to_string())),
+        )
+    }
+}
+
+/// Parses `key=value` command line arguments.
+pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected key=value, got `{}`", s))
+}
+
+/// The deltas of a streamed answer. An error ends the stream early, e.g. when the
+/// connection drops or the API sends something that isn't a delta.
+pub type Stream = Receiver<Result<ChatCompletionDelta>>;
+
+/// Sends a streaming chat completion request with `sampling` applied and `extra` merged
+/// into the body, shaped for what the model accepts (see `capabilities`).
+/// Rate limits, server errors and connection failures are retried according to `policy`.
+pub async fn open_stream(
+    builder: ChatCompletionBuilder,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    sampling: &Sampling,
+    policy: &RetryPolicy,
+) -> Result<Stream> {
+    let request = sampling.apply(builder).stream(true).build()?;
+    let mut body = serde_json::to_value(&request)?;
+    if let Value::Object(fields) = &mut body {
+        fields.extend(extra.body.clone());
+        shape(fields, sampling);
+    }
+
+    let model = body
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let messages = body
+        .get("messages")
+        .and_then(Value::as_array)
+        .map_or(0, Vec::len);
+    debug!(
+        model = model.as_str(),
+        url = credentials.base_url(),
+        messages,
+        "request"
+    );
+    let started = Instant::now();
+
+    if let Some(events) = cassette::replay(&body)? {
+        let (tx, rx) = channel(32);
+        tokio::spawn(async move {
+            for data in events.iter().take_while(|data| *data != "[DONE]") {
+                let delta = serde_json::from_str(data)
+                    .with_context(|| format!("Invalid event in the cassette: {}", data));
+                let failed = delta.is_err();
+                if tx.send(delta).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        return Ok(rx);
+    }
+
+    let mut attempt = 1;
+    let events = loop {
+        let reply = extra
+            .transport
+            .stream(
+                &format!("{}chat/completions", credentials.base_url()),
+                credentials.api_key(),
+                &extra.headers,
+                &body,
+            )
+            .await?;
+
+        let (reason, summary, retry_after) = match reply {
+            Reply::Events(events) => {
+                info!(
+                    model = model.as_str(),
+                    attempt,
+                    latency_ms = started.elapsed().as_millis() as u64,
+                    "stream opened"
+                );
+                break events;
+            }
+            Reply::Refused {
+                status,
+                headers,
+                body,
+            } => {
+                if !retry::is_retryable(status) && !retry::is_overloaded(&body) {
+                    bail!("API returned {}: {}", status, body)
+                }
+                (
+                    format!("API returned {}: {}", status, body),
+                    retry::describe(status, &body),
+                    retry::retry_after(&headers),
+                )
+            }
+            Reply::Unreachable(reason) => (reason, String::from("Connection failed"), None),
+        };
+        info!(
+            model = model.as_str(),
+            attempt,
+            reason = reason.as_str(),
+            "request failed"
+        );
+
+        if attempt >= policy.max_attempts {
+            bail!("Giving up after {} attempts. {}", attempt, reason)
+        }
+        attempt += 1;
+        retry::wait(
+            &summary,
+            policy.delay(attempt - 1, retry_after),
+            attempt,
+            policy.max_attempts,
+        )
+        .await;
+    };
+
+    let (tx, rx) = channel(32);
+    let recorded = cassette::recording().then_some(body);
+    tokio::spawn(forward(events, tx, recorded));
+    Ok(rx)
+}
+
+/// Adjusts a request body to the capabilities of its model.
+fn shape(fields: &mut Map<String, Value>, sampling: &Sampling) {
+    let capabilities = capabilities(fields.get("model").and_then(Value::as_str).unwrap_or(""));
+    if !capabilities.sampling {
+        fields.remove("temperature");
+        fields.remove("top_p");
+    }
+    if capabilities.reasoning {
+        if let Some(max_tokens) = fields.remove("max_tokens") {
+            fields.insert(String::from("max_completion_tokens"), max_tokens);
+        }
+        if let Some(effort) = sampling.reasoning_effort {
+            fields.insert(
+                String::from("reasoning_effort"),
+                Value::String(effort.as_str().to_string()),
+            );
+        }
+    }
+    if !capabilities.system_role {
+        if let Some(Value::Array(messages)) = fields.get_mut("messages") {
+            for message in messages {
+                if message.get("role").and_then(Value::as_str) == Some("system") {
+                    message["role"] = Value::String(String::from("user"));
+                }
+            }
+        }
+    }
+}
+
+/// Passes the streamed deltas on; with `recorded` set the events are added to the cassette.
+/// Only `[DONE]` or the server closing the stream ends it cleanly; any other error is
+/// passed on as the last item so the answer isn't taken for complete.
+async fn forward(
+    mut source: Events,
+    tx: Sender<Result<ChatCompletionDelta>>,
+    recorded: Option<Value>,
+) {
+    let mut events = Vec::new();
+    while let Some(data) = source.next().await {
+        let delta = match data {
+            Ok(data) if data == "[DONE]" => {
+                events.push(data);
+                break;
+            }
+            Ok(data) => {
+                let delta = serde_json::from_str(&data)
+                    .with_context(|| format!("Invalid event from the API: {}", data));
+                events.push(data);
+                delta
+            }
+            Err(e) => Err(e),
+        };
+        let failed = delta.is_err();
+        if tx.send(delta).await.is_err() || failed {
+            break;
+        }
+    }
+    if let Some(request) = recorded {
+        if let Err(e) = cassette::record(request, events) {
+            warn!("{:#}", e);
+        }
+    }
+}
+
+/// Gathers the whole completion, passing each piece of the first answer to `on_delta`
+/// as it arrives. Returning `ControlFlow::Break` ends the answer there; the stream is
+/// dropped, which closes the connection so the rest isn't generated. Nothing is printed.
+/// An error in the stream is returned even if part of the answer arrived.
+pub async fn ask(
+    mut stream: Stream,
+    mut on_delta: impl FnMut(&str) -> ControlFlow<()>,
+) -> Result<ChatCompletion> {
+    let started = Instant::now();
     let mut merged: Option<ChatCompletionDelta> = None;
 
     while let Some(delta) = stream.recv().await {
-        let choice = &delta.choices[0];
-        if let Some(content) = &choice.delta.content {
-            print!("{}", content);
+        let delta = delta?;
+        let flow = match delta
+            .choices
+            .iter()
+            .find(|choice| choice.index == 0)
+            .and_then(|choice| choice.delta.content.as_deref())
+        {
+            Some(content) => on_delta(content),
+            None => ControlFlow::Continue(()),
+        };
+        merge(&mut merged, delta)?;
+        if flow.is_break() {
+            break;
         }
-        if let Some(_) = &choice.finish_reason {
-            println!("");
+    }
+
+    completion(merged, started)
+}
+
+/// Like `ask`, but only shows a spinner while the answer is gathered.
+pub async fn collect(stream: Stream) -> Result<ChatCompletion> {
+    let _spinner = Spinner::start();
+    ask(stream, |_| ControlFlow::Continue(())).await
+}
+
+/// All answers of a completion as a numbered list.
+pub fn numbered_choices(completion: &ChatCompletion) -> String {
+    completion
+        .choices
+        .iter()
+        .map(|choice| {
+            format!(
+                "[{}]\n{}",
+                choice.index + 1,
+                choice.message.content.as_deref().unwrap_or_default().trim()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Whether no answer has any text.
+pub fn is_empty(completion: &ChatCompletion) -> bool {
+    completion.choices.iter().all(|choice| {
+        choice
+            .message
+            .content
+            .as_deref()
+            .is_none_or(|content| content.trim().is_empty())
+    })
+}
+
+/// The finish reason when every answer was refused or stopped by the content filter.
+pub fn refusal(completion: &ChatCompletion) -> Option<&str> {
+    let reasons = completion
+        .choices
+        .iter()
+        .map(|choice| choice.finish_reason.as_str())
+        .collect::<Vec<_>>();
+    reasons
+        .iter()
+        .all(|reason| matches!(*reason, "content_filter" | "refusal"))
+        .then(|| reasons.first().copied())
+        .flatten()
+}
+
+/// `ChatCompletionDelta::merge` drops choices the first chunk didn't have,
+/// so answers other than the first (`n > 1`) are added here.
+fn merge(merged: &mut Option<ChatCompletionDelta>, mut delta: ChatCompletionDelta) -> Result<()> {
+    match merged.as_mut() {
+        Some(merged) => {
+            let (new, known): (Vec<_>, Vec<_>) = delta.choices.into_iter().partition(|choice| {
+                merged
+                    .choices
+                    .iter()
+                    .all(|existing| existing.index != choice.index)
+            });
+            merged.choices.extend(new);
+            delta.choices = known;
+            merged.merge(delta)?;
+        }
+        None => *merged = Some(delta),
+    }
+    Ok(())
+}
+
+fn completion(merged: Option<ChatCompletionDelta>, started: Instant) -> Result<ChatCompletion> {
+    let mut completion: ChatCompletion = merged
+        .map(Into::into)
+        .with_context(|| "The stream ended without a response")?;
+    completion.choices.sort_by_key(|choice| choice.index);
+    let answer = completion
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.as_deref())
+        .unwrap_or_default();
+    info!(
+        id = completion.id.as_str(),
+        model = completion.model.as_str(),
+        finish_reason = completion
+            .choices
+            .first()
+            .map_or("", |choice| choice.finish_reason.as_str()),
+        choices = completion.choices.len(),
+        duration_ms = started.elapsed().as_millis() as u64,
+        prompt_tokens = completion.usage.as_ref().map(|usage| usage.prompt_tokens),
+        completion_tokens = completion
+            .usage
+            .as_ref()
+            .map_or(estimate_tokens(answer) as u32, |usage| usage
+                .completion_tokens),
+        "response"
+    );
+    Ok(completion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Download;
+    use futures_util::future::BoxFuture;
+    use futures_util::stream;
+    use openai::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+    use reqwest::StatusCode;
+
+    /// Answers every request with `events`, or refuses it with `status`.
+    #[derive(Debug)]
+    struct Mock {
+        events: Vec<&'static str>,
+        status: Option<StatusCode>,
+    }
+
+    impl ApiTransport for Mock {
+        fn stream(
+            &self,
+            _url: &str,
+            _api_key: &str,
+            _headers: &Headers,
+            _body: &Value,
+        ) -> BoxFuture<'static, Result<Reply>> {
+            let reply = match self.status {
+                Some(status) => Reply::Refused {
+                    status,
+                    headers: Default::default(),
+                    body: String::from("{}"),
+                },
+                None => Reply::Events(
+                    stream::iter(self.events.clone())
+                        .map(|data| Ok(data.to_string()))
+                        .boxed(),
+                ),
+            };
+            Box::pin(async move { Ok(reply) })
         }
-        stdout().flush()?;
 
-        match merged.as_mut() {
-            Some(c) => c.merge(delta)?,
-            None => merged = Some(delta),
-        };
+        fn download(&self, url: &str, _limit: usize) -> BoxFuture<'static, Result<Download>> {
+            let url = url.to_string();
+            Box::pin(async move { bail!("Can't fetch {}", url) })
+        }
+    }
+
+    async fn send(mock: Mock) -> Result<ChatCompletion> {
+        let messages = vec![ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(String::from("Hi")),
+            ..Default::default()
+        }];
+        let stream = open_stream(
+            ChatCompletionDelta::builder("gpt-4o", messages),
+            &Credentials::new("key", "http://mock/"),
+            &RequestExtra::default().transport(Arc::new(mock)),
+            &Sampling::default(),
+            &RetryPolicy::default(),
+        )
+        .await?;
+        collect(stream).await
     }
 
-    Ok(merged.unwrap().into())
+    #[tokio::test]
+    async fn answers_come_through_the_transport() {
+        let completion = send(Mock {
+            events: vec![
+                r#"{"id":"x","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"role":"assistant","content":"Hel"},"finish_reason":null}]}"#,
+                r#"{"id":"x","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"content":"lo"},"finish_reason":"stop"}]}"#,
+                "[DONE]",
+            ],
+            status: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            completion.choices[0].message.content.as_deref(),
+            Some("Hello")
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_requests_are_errors() {
+        let error = send(Mock {
+            events: Vec::new(),
+            status: Some(StatusCode::BAD_REQUEST),
+        })
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("400"), "{}", error);
+    }
 }
@@ -0,0 +1,149 @@
+use crate::chunk::estimate_tokens;
+
+/// The part of a unified diff that belongs to a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub path: String,
+    pub text: String,
+}
+
+/// Splits `git diff` output at its `diff --git` headers.
+pub fn split_files(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    for line in diff.split_inclusive('\n') {
+        match line.strip_prefix("diff --git ") {
+            Some(header) => files.push(FileDiff {
+                path: header
+                    .trim_end()
+                    .rsplit_once(" b/")
+                    .map(|(_, path)| path.to_string())
+                    .unwrap_or_else(|| header.trim_end().to_string()),
+                text: line.to_string(),
+            }),
+            None => match files.last_mut() {
+                Some(file) => file.text.push_str(line),
+                None => files.push(FileDiff {
+                    path: String::new(),
+                    text: line.to_string(),
+                }),
+            },
+        }
+    }
+    files
+}
+
+/// Shrinks a diff to roughly `max_tokens`, giving every file an equal share so a
+/// single huge file (lockfiles, generated code) can't crowd out the others.
+pub fn truncate(diff: &str, max_tokens: usize) -> String {
+    if estimate_tokens(diff) <= max_tokens {
+        return diff.to_string();
+    }
+    let files = split_files(diff);
+    let share = max_tokens / files.len().max(1);
+    files
+        .iter()
+        .map(|file| truncate_lines(&file.text, share))
+        .collect()
+}
+
+fn truncate_lines(text: &str, max_tokens: usize) -> String {
+    let mut kept = String::new();
+    let mut used = 0;
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    for (i, line) in lines.iter().enumerate() {
+        used += estimate_tokens(line);
+        if used > max_tokens && i > 0 {
+            kept.push_str(&format!("[... {} more lines truncated]\n", lines.len() - i));
+            break;
+        }
+        kept.push_str(line);
+    }
+    kept
+}
+
+/// A single `@@` hunk together with the file it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub path: String,
+    /// First line of the hunk in the new version of the file.
+    pub start_line: usize,
+    pub text: String,
+}
+
+impl Hunk {
+    /// The hunk with new-file line numbers in front of context and added lines,
+    /// so answers can point at real lines instead of counting from the header.
+    pub fn numbered(&self) -> String {
+        let mut out = String::new();
+        let mut line = self.start_line;
+        for (i, text) in self.text.lines().enumerate() {
+            if i == 0 || text.starts_with('-') || text.starts_with('\\') {
+                out.push_str(&format!("{:>6} {}\n", "", text));
+            } else {
+                out.push_str(&format!("{:>6} {}\n", line, text));
+                line += 1;
+            }
+        }
+        out
+    }
+}
+
+/// Splits a unified diff (from `git diff` or `diff -u`) into its hunks.
+pub fn hunks(diff: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut path = String::new();
+    // Lines still expected in the current hunk, for the old and the new side.
+    let (mut old, mut new) = (0usize, 0usize);
+    for line in diff.split_inclusive('\n') {
+        if old > 0 || new > 0 {
+            match line.chars().next() {
+                Some('-') => old = old.saturating_sub(1),
+                Some('+') => new = new.saturating_sub(1),
+                Some('\\') => {}
+                _ => {
+                    old = old.saturating_sub(1);
+                    new = new.saturating_sub(1);
+                }
+            }
+            if let Some(hunk) = hunks.last_mut() {
+                hunk.text.push_str(line);
+            }
+        } else if let Some(header) = line.strip_prefix("diff --git ") {
+            path = header
+                .trim_end()
+                .rsplit_once(" b/")
+                .map(|(_, path)| path.to_string())
+                .unwrap_or_default();
+        } else if let Some(name) = line.strip_prefix("+++ ") {
+            let name = name.split('\t').next().unwrap_or_default().trim_end();
+            if name != "/dev/null" {
+                path = name.strip_prefix("b/").unwrap_or(name).to_string();
+            }
+        } else if let Some((old_range, new_range)) = hunk_ranges(line) {
+            (old, new) = (old_range.1, new_range.1);
+            hunks.push(Hunk {
+                path: path.clone(),
+                start_line: new_range.0.max(1),
+                text: line.to_string(),
+            });
+        } else if line.starts_with('\\') {
+            // "\ No newline at end of file" right after the last line of a hunk.
+            if let Some(hunk) = hunks.last_mut() {
+                hunk.text.push_str(line);
+            }
+        }
+    }
+    hunks
+}
+
+/// Reads `(a, b)` and `(c, d)` from a `@@ -a,b +c,d @@` header. Counts default to 1.
+fn hunk_ranges(header: &str) -> Option<((usize, usize), (usize, usize))> {
+    let mut parts = header.strip_prefix("@@ ")?.split_whitespace();
+    let range = |part: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let mut numbers = part?.strip_prefix(sign)?.split(',');
+        let start = numbers.next()?.parse().ok()?;
+        let count = numbers.next().map_or(Some(1), |n| n.parse().ok())?;
+        Some((start, count))
+    };
+    Some((range(parts.next(), '-')?, range(parts.next(), '+')?))
+}
@@ -0,0 +1,741 @@
+use crate::chunk::{estimate_tokens, Chunker};
+use crate::transport::{ApiTransport, Download};
+use anyhow::{anyhow, bail, Context, Result};
+use miniz_oxide::inflate::{decompress_to_vec_with_limit, decompress_to_vec_zlib_with_limit};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{read, read_to_string};
+use std::path::Path;
+
+/// Upper bound for a single decompressed stream or archive member.
+const MAX_INFLATED: usize = 64 * 1024 * 1024;
+
+/// Upper bound for a page downloaded with `fetch`.
+const MAX_FETCHED: usize = 16 * 1024 * 1024;
+
+/// Reads a file as text. PDF, DOCX and HTML are converted, keeping page breaks
+/// and headings; anything else is read as UTF-8.
+pub fn read_text(path: &Path) -> Result<String> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    let bytes = || read(path).with_context(|| format!("Can't read {}", path.display()));
+    match extension.as_deref() {
+        Some("pdf") => pdf_text(&bytes()?),
+        Some("docx") => docx_text(&bytes()?),
+        Some("html") | Some("htm") | Some("xhtml") => {
+            Ok(html_text(&read_to_string(path).with_context(|| {
+                format!("Can't read {}", path.display())
+            })?))
+        }
+        _ => read_to_string(path).with_context(|| format!("Can't read {}", path.display())),
+    }
+    .with_context(|| format!("Can't extract text from {}", path.display()))
+}
+
+/// Downloads `url` through `transport` and returns its readable text, headed by the page
+/// title and the URL. HTML is narrowed to its `<main>` or `<article>` when there is one;
+/// PDFs are converted. Responses over `MAX_FETCHED` bytes are refused.
+pub async fn fetch(transport: &dyn ApiTransport, url: &str) -> Result<String> {
+    let Download { content_type, body } = transport.download(url, MAX_FETCHED).await?;
+    let text = if content_type.contains("pdf") {
+        pdf_text(&body)?
+    } else if content_type.contains("html") || content_type.is_empty() {
+        page_text(&String::from_utf8_lossy(&body))
+    } else {
+        String::from_utf8_lossy(&body).into_owned()
+    };
+    Ok(format!("[source: {}]\n{}", url, text))
+}
+
+/// Readable text of a web page: the title, then the `<main>` or `<article>` element
+/// when present (so menus and sidebars around it are left out), otherwise the whole page.
+pub fn page_text(html: &str) -> String {
+    let bytes = html.as_bytes();
+    let title = find(bytes, b"<title", true).and_then(|start| {
+        let start = start + html[start..].find('>')? + 1;
+        let end = start + find(&bytes[start..], b"</title", true)?;
+        Some(unescape(html[start..end].trim()))
+    });
+    let body = ["main", "article"].iter().find_map(|tag| {
+        let start = find(bytes, format!("<{}", tag).as_bytes(), true)?;
+        let end = find(bytes, format!("</{}>", tag).as_bytes(), true)?;
+        (start < end).then(|| &html[start..end])
+    });
+    let text = html_text(body.unwrap_or(html));
+    match title.filter(|title| !title.is_empty()) {
+        Some(title) => format!("# {}\n\n{}", title, text),
+        None => text,
+    }
+}
+
+/// Splits a file's text into messages of at most `max_tokens` each, labelled with the
+/// file name and line range when there is more than one.
+pub fn parts(name: &str, text: &str, max_tokens: usize) -> Vec<String> {
+    if estimate_tokens(text) <= max_tokens {
+        return vec![text.to_string()];
+    }
+    let chunks = Chunker::new(max_tokens, 0).split(text);
+    let count = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            format!(
+                "[{} part {}/{}, lines {}-{}]\n{}",
+                name,
+                i + 1,
+                count,
+                chunk.start_line,
+                chunk.end_line,
+                chunk.text
+            )
+        })
+        .collect()
+}
+
+/// Text of a PDF, one `[page N]` section per page that shows text. Pages are found
+/// through the `/Pages` tree; text in streams no page lists as its contents (form
+/// XObjects, or every stream when the page objects are compressed) follows in `[section]`s.
+/// Only the standard text operators are understood; fonts with custom encodings
+/// (common for CJK documents) come out as whatever bytes they use.
+pub fn pdf_text(pdf: &[u8]) -> Result<String> {
+    if !pdf.starts_with(b"%PDF") {
+        bail!("Not a PDF file")
+    }
+    let objects = pdf_objects(pdf);
+    let mut texts = objects
+        .iter()
+        .filter_map(|(number, object)| {
+            let content = inflate(object.dictionary, object.stream?)?;
+            let text = content_text(&content);
+            (!text.trim().is_empty()).then_some((*number, text))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut sections = Vec::new();
+    for (index, page) in pdf_pages(&objects).iter().enumerate() {
+        let text = refs(objects[page].dictionary, b"/Contents")
+            .iter()
+            .filter_map(|contents| texts.remove(contents))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !text.trim().is_empty() {
+            sections.push(format!("[page {}]\n{}", index + 1, text.trim()));
+        }
+    }
+    let numbered = !sections.is_empty();
+    for (index, text) in texts.values().enumerate() {
+        sections.push(match numbered {
+            true => format!("[section]\n{}", text.trim()),
+            false => format!("[section {}]\n{}", index + 1, text.trim()),
+        });
+    }
+    if sections.is_empty() {
+        bail!("No extractable text (the PDF may be scanned or use encrypted content)")
+    }
+    Ok(sections.join("\n\n"))
+}
+
+/// An indirect object of a PDF: its dictionary and, for streams, the raw stream data.
+struct PdfObject<'a> {
+    dictionary: &'a [u8],
+    stream: Option<&'a [u8]>,
+}
+
+/// The indirect objects of a PDF by number; a later definition (an incremental update)
+/// replaces an earlier one.
+fn pdf_objects(pdf: &[u8]) -> HashMap<u32, PdfObject<'_>> {
+    let mut objects = HashMap::new();
+    let mut offset = 0;
+    while let Some(found) = find(&pdf[offset..], b"obj", false) {
+        let start = offset + found;
+        offset = start + b"obj".len();
+        if pdf[..start].ends_with(b"end") {
+            continue;
+        }
+        let Some(number) = object_number(&pdf[..start]) else {
+            continue;
+        };
+        let body = &pdf[offset..];
+        let end = find(body, b"endobj", false).unwrap_or(body.len());
+        let object = match find(&body[..end], b"stream", false) {
+            Some(stream) => {
+                let mut data = stream + b"stream".len();
+                if body[data..].starts_with(b"\r\n") {
+                    data += 2;
+                } else if body[data..].starts_with(b"\n") || body[data..].starts_with(b"\r") {
+                    data += 1;
+                }
+                // The stream may contain `endobj` by chance; `endstream` ends it.
+                let Some(length) = find(&body[data..], b"endstream", false) else {
+                    break;
+                };
+                offset += data + length + b"endstream".len();
+                PdfObject {
+                    dictionary: &body[..stream],
+                    stream: Some(&body[data..data + length]),
+                }
+            }
+            None => {
+                offset += end;
+                PdfObject {
+                    dictionary: &body[..end],
+                    stream: None,
+                }
+            }
+        };
+        objects.insert(number, object);
+    }
+    objects
+}
+
+/// The object number of `N G obj`, given the bytes before `obj`.
+fn object_number(before: &[u8]) -> Option<u32> {
+    let text = std::str::from_utf8(&before[before.len().saturating_sub(24)..]).ok()?;
+    let mut words = text.split_ascii_whitespace().rev();
+    let generation = words.next()?;
+    let number = words.next()?;
+    generation.parse::<u32>().ok()?;
+    number
+        .rsplit(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Page objects in reading order: the leaves of the `/Pages` tree, or every page object
+/// in file order when there's no readable tree.
+fn pdf_pages(objects: &HashMap<u32, PdfObject>) -> Vec<u32> {
+    fn walk(objects: &HashMap<u32, PdfObject>, node: u32, depth: usize, pages: &mut Vec<u32>) {
+        let Some(object) = objects.get(&node).filter(|_| depth < 64) else {
+            return;
+        };
+        if has_type(object.dictionary, b"Page") {
+            pages.push(node);
+        } else if has_type(object.dictionary, b"Pages") {
+            for kid in refs(object.dictionary, b"/Kids") {
+                walk(objects, kid, depth + 1, pages);
+            }
+        }
+    }
+    let mut pages = Vec::new();
+    let root = objects.iter().find(|(_, object)| {
+        has_type(object.dictionary, b"Pages")
+            && find(object.dictionary, b"/Parent", false).is_none()
+    });
+    if let Some((root, _)) = root {
+        walk(objects, *root, 0, &mut pages);
+    }
+    if pages.is_empty() {
+        pages = objects
+            .iter()
+            .filter(|(_, object)| has_type(object.dictionary, b"Page"))
+            .map(|(number, _)| *number)
+            .collect();
+        pages.sort_unstable();
+    }
+    pages
+}
+
+/// Whether `dictionary` has `/Type /<name>` (with or without the space).
+fn has_type(dictionary: &[u8], name: &[u8]) -> bool {
+    let mut rest = dictionary;
+    while let Some(at) = find(rest, b"/Type", false) {
+        rest = &rest[at + b"/Type".len()..];
+        let value = rest.trim_ascii_start();
+        if let Some(value) = value.strip_prefix(b"/") {
+            let end = value
+                .iter()
+                .position(|b| !b.is_ascii_alphanumeric())
+                .unwrap_or(value.len());
+            if &value[..end] == name {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// The object numbers referenced (`N G R`) by the value of `key`, a single reference
+/// or an array of them.
+fn refs(dictionary: &[u8], key: &[u8]) -> Vec<u32> {
+    let Some(at) = find(dictionary, key, false) else {
+        return Vec::new();
+    };
+    let value = dictionary[at + key.len()..].trim_ascii_start();
+    let value = match value.strip_prefix(b"[") {
+        Some(array) => &array[..array.iter().position(|b| *b == b']').unwrap_or(array.len())],
+        None => &value[..value.len().min(24)],
+    };
+    let words = String::from_utf8_lossy(value);
+    let words = words.split_ascii_whitespace().collect::<Vec<_>>();
+    let mut numbers = Vec::new();
+    for window in words.windows(3) {
+        if window[2].starts_with('R') && window[1].parse::<u32>().is_ok() {
+            if let Ok(number) = window[0].parse() {
+                numbers.push(number);
+            }
+        }
+    }
+    numbers
+}
+
+/// The decoded data of a stream, or `None` for images and other encodings that carry no
+/// text we can read.
+fn inflate(dictionary: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    if find(dictionary, b"/FlateDecode", false).is_some() {
+        decompress_to_vec_zlib_with_limit(data, MAX_INFLATED).ok()
+    } else if find(dictionary, b"/Filter", false).is_none() {
+        Some(data.to_vec())
+    } else {
+        None
+    }
+}
+
+/// Interprets the text showing operators of a content stream.
+fn content_text(content: &[u8]) -> String {
+    let mut text = String::new();
+    let mut operands: Vec<Operand> = Vec::new();
+    let mut i = 0;
+    while i < content.len() {
+        match content[i] {
+            b'(' => {
+                let (string, end) = literal_string(content, i + 1);
+                operands.push(Operand::String(string));
+                i = end;
+            }
+            b'<' if content.get(i + 1) != Some(&b'<') => {
+                let end = content[i..]
+                    .iter()
+                    .position(|b| *b == b'>')
+                    .map_or(content.len(), |p| i + p);
+                operands.push(Operand::String(hex_string(&content[i + 1..end])));
+                i = end + 1;
+            }
+            b'[' => {
+                operands.push(Operand::ArrayStart);
+                i += 1;
+            }
+            b']' => {
+                let start = operands
+                    .iter()
+                    .rposition(|o| matches!(o, Operand::ArrayStart))
+                    .unwrap_or(0);
+                let items = operands.split_off(start);
+                operands.push(Operand::Array(items.into_iter().skip(1).collect()));
+                i += 1;
+            }
+            b'%' => {
+                while i < content.len() && content[i] != b'\n' && content[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            c if c.is_ascii_whitespace() => i += 1,
+            _ => {
+                let end = content[i..]
+                    .iter()
+                    .position(|b| b.is_ascii_whitespace() || b"()<>[]/%".contains(b))
+                    .map_or(content.len(), |p| i + p)
+                    .max(i + 1);
+                let token = &content[i..end];
+                i = end;
+                if let Ok(number) = std::str::from_utf8(token).unwrap_or("").parse::<f64>() {
+                    operands.push(Operand::Number(number));
+                    continue;
+                }
+                match token {
+                    b"Tj" | b"'" | b"\"" => {
+                        if token != b"Tj" {
+                            text.push('\n');
+                        }
+                        if let Some(Operand::String(s)) = operands.last() {
+                            text.push_str(&decode(s));
+                        }
+                    }
+                    b"TJ" => {
+                        if let Some(Operand::Array(items)) = operands.last() {
+                            for item in items {
+                                match item {
+                                    Operand::String(s) => text.push_str(&decode(s)),
+                                    // Large negative kerning is how many PDFs encode spaces.
+                                    Operand::Number(n) if *n < -200.0 => text.push(' '),
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                    b"T*" | b"ET" => text.push('\n'),
+                    b"Td" | b"TD" => {
+                        let y = match operands.as_slice() {
+                            [.., Operand::Number(_), Operand::Number(y)] => *y,
+                            _ => 0.0,
+                        };
+                        if y != 0.0 {
+                            text.push('\n');
+                        } else if !text.ends_with([' ', '\n']) {
+                            text.push(' ');
+                        }
+                    }
+                    _ => {}
+                }
+                operands.clear();
+            }
+        }
+    }
+    // Collapse the blank lines left by text objects that ended right after a line break.
+    let mut collapsed = String::with_capacity(text.len());
+    for line in text.lines() {
+        if !(line.trim().is_empty() && collapsed.ends_with("\n\n")) {
+            collapsed.push_str(line.trim_end());
+            collapsed.push('\n');
+        }
+    }
+    collapsed
+}
+
+enum Operand {
+    Number(f64),
+    String(Vec<u8>),
+    ArrayStart,
+    Array(Vec<Operand>),
+}
+
+fn literal_string(content: &[u8], mut i: usize) -> (Vec<u8>, usize) {
+    let mut string = Vec::new();
+    let mut depth = 0;
+    while i < content.len() {
+        match content[i] {
+            b'\\' => {
+                i += 1;
+                match content.get(i) {
+                    Some(b'n') => string.push(b'\n'),
+                    Some(b'r') => string.push(b'\r'),
+                    Some(b't') => string.push(b'\t'),
+                    Some(b'b') | Some(b'f') => {}
+                    Some(d @ b'0'..=b'7') => {
+                        let mut value = u32::from(d - b'0');
+                        for _ in 0..2 {
+                            match content.get(i + 1) {
+                                Some(d @ b'0'..=b'7') => {
+                                    value = value * 8 + u32::from(d - b'0');
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        string.push(value as u8);
+                    }
+                    Some(b'\r') | Some(b'\n') => {}
+                    Some(c) => string.push(*c),
+                    None => {}
+                }
+            }
+            b'(' => {
+                depth += 1;
+                string.push(b'(');
+            }
+            b')' if depth == 0 => return (string, i + 1),
+            b')' => {
+                depth -= 1;
+                string.push(b')');
+            }
+            c => string.push(c),
+        }
+        i += 1;
+    }
+    (string, i)
+}
+
+fn hex_string(hex: &[u8]) -> Vec<u8> {
+    let digits = hex
+        .iter()
+        .filter_map(|c| (*c as char).to_digit(16))
+        .collect::<Vec<_>>();
+    digits
+        .chunks(2)
+        .map(|pair| (pair[0] * 16 + pair.get(1).copied().unwrap_or(0)) as u8)
+        .collect()
+}
+
+/// PDF strings are either UTF-16BE with a byte order mark or a single-byte encoding.
+fn decode(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xfe, 0xff]) {
+        let units = utf16
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+            .collect::<Vec<_>>();
+        return String::from_utf16_lossy(&units);
+    }
+    bytes
+        .iter()
+        .filter(|b| !b.is_ascii_control() || b.is_ascii_whitespace())
+        .map(|b| *b as char)
+        .collect()
+}
+
+/// Text of `word/document.xml`, with headings as `#` lines and list items as `- ` lines.
+pub fn docx_text(docx: &[u8]) -> Result<String> {
+    let document = zip_entry(docx, "word/document.xml")?;
+    let xml = String::from_utf8_lossy(&document);
+    let mut text = String::new();
+    for paragraph in xml.split("</w:p>") {
+        let Some(start) = paragraph
+            .rfind("<w:p>")
+            .or_else(|| paragraph.rfind("<w:p "))
+        else {
+            continue;
+        };
+        let paragraph = &paragraph[start..];
+        let style = attribute(paragraph, "<w:pStyle ", "w:val").unwrap_or_default();
+        let prefix = if style == "Title" {
+            String::from("# ")
+        } else if let Some(level) = style
+            .strip_prefix("Heading")
+            .and_then(|l| l.parse::<usize>().ok())
+        {
+            format!("{} ", "#".repeat((level + 1).min(6)))
+        } else if paragraph.contains("<w:numPr>") {
+            String::from("- ")
+        } else {
+            String::new()
+        };
+
+        let mut line = String::new();
+        let mut rest = paragraph;
+        while let Some(open) = rest.find('<') {
+            let Some(close) = rest[open..].find('>').map(|c| open + c) else {
+                break;
+            };
+            let tag = &rest[open + 1..close];
+            rest = &rest[close + 1..];
+            if tag == "w:t" || tag.starts_with("w:t ") {
+                let end = rest.find("</w:t>").unwrap_or(rest.len());
+                line.push_str(&unescape(&rest[..end]));
+                rest = &rest[end..];
+            } else if tag.starts_with("w:tab") {
+                line.push('\t');
+            } else if tag.starts_with("w:br") || tag.starts_with("w:cr") {
+                line.push('\n');
+            }
+        }
+        if !line.trim().is_empty() {
+            text.push_str(&prefix);
+            text.push_str(&line);
+            text.push_str(if prefix.starts_with('#') {
+                "\n\n"
+            } else {
+                "\n"
+            });
+        }
+    }
+    Ok(text)
+}
+
+/// Reads one member of a ZIP archive through its central directory.
+fn zip_entry(zip: &[u8], name: &str) -> Result<Vec<u8>> {
+    let u16_at = |at: usize| -> Result<usize> {
+        zip.get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| anyhow!("Truncated archive"))
+    };
+    let u32_at = |at: usize| -> Result<usize> {
+        zip.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(|| anyhow!("Truncated archive"))
+    };
+
+    let end = rfind(zip, b"PK\x05\x06").with_context(|| "Not a ZIP archive")?;
+    let entries = u16_at(end + 10)?;
+    let mut at = u32_at(end + 16)?;
+    for _ in 0..entries {
+        if zip.get(at..at + 4) != Some(b"PK\x01\x02") {
+            bail!("Broken central directory")
+        }
+        let method = u16_at(at + 10)?;
+        let compressed = u32_at(at + 20)?;
+        let name_len = u16_at(at + 28)?;
+        let extra_len = u16_at(at + 30)?;
+        let comment_len = u16_at(at + 32)?;
+        let local = u32_at(at + 42)?;
+        let entry_name = zip
+            .get(at + 46..at + 46 + name_len)
+            .ok_or_else(|| anyhow!("Truncated archive"))?;
+        if entry_name == name.as_bytes() {
+            let data = local + 30 + u16_at(local + 26)? + u16_at(local + 28)?;
+            let data = zip
+                .get(data..data + compressed)
+                .ok_or_else(|| anyhow!("Truncated archive"))?;
+            return match method {
+                0 => Ok(data.to_vec()),
+                8 => decompress_to_vec_with_limit(data, MAX_INFLATED)
+                    .map_err(|e| anyhow!("Can't inflate {}: {:?}", name, e.status)),
+                _ => bail!("Unsupported compression method {}", method),
+            };
+        }
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    bail!("{} not found in the archive", name)
+}
+
+/// Readable text of an HTML page: scripts and styles dropped, headings as `#` lines,
+/// list items as `- ` lines and `<pre>` blocks kept verbatim.
+pub fn html_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    let mut pre = false;
+    while !rest.is_empty() {
+        let Some(open) = rest.find('<') else {
+            push_html_text(&mut text, rest, pre);
+            break;
+        };
+        push_html_text(&mut text, &rest[..open], pre);
+        rest = &rest[open..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let Some(close) = rest.find('>') else {
+            break;
+        };
+        let tag = rest[1..close].trim().to_lowercase();
+        rest = &rest[close + 1..];
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        match name.as_str() {
+            "script" | "style" | "head" | "noscript" | "template" | "nav" | "footer" | "aside"
+            | "form"
+                if !closing =>
+            {
+                let end = format!("</{}", name);
+                rest = find(rest.as_bytes(), end.as_bytes(), true).map_or("", |i| &rest[i..]);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                new_block(&mut text);
+                if !closing {
+                    let level = name[1..].parse().unwrap_or(1);
+                    text.push_str(&format!("{} ", "#".repeat(level)));
+                }
+            }
+            "li" if !closing => {
+                new_line(&mut text);
+                text.push_str("- ");
+            }
+            "pre" => {
+                pre = !closing;
+                new_block(&mut text);
+            }
+            "br" => text.push('\n'),
+            "p" | "div" | "section" | "article" | "table" | "ul" | "ol" | "blockquote" => {
+                new_block(&mut text)
+            }
+            "tr" => new_line(&mut text),
+            "td" | "th" if !closing => text.push('\t'),
+            _ => {}
+        }
+    }
+    text.trim().to_string() + "\n"
+}
+
+fn push_html_text(text: &mut String, raw: &str, pre: bool) {
+    let decoded = unescape(raw);
+    if pre {
+        text.push_str(&decoded);
+        return;
+    }
+    for (i, word) in decoded.split_whitespace().enumerate() {
+        let starts_with_space = i > 0 || decoded.starts_with(char::is_whitespace);
+        if starts_with_space && !text.is_empty() && !text.ends_with([' ', '\n', '\t']) {
+            text.push(' ');
+        }
+        text.push_str(word);
+    }
+    if decoded.ends_with(char::is_whitespace) && !text.ends_with([' ', '\n', '\t']) {
+        text.push(' ');
+    }
+}
+
+fn new_line(text: &mut String) {
+    let trimmed = text.trim_end_matches([' ', '\t']).len();
+    text.truncate(trimmed);
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+fn new_block(text: &mut String) {
+    new_line(text);
+    if !text.is_empty() && !text.ends_with("\n\n") {
+        text.push('\n');
+    }
+}
+
+/// Decodes the XML/HTML character references that show up in documents.
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|n| n.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn attribute(text: &str, tag: &str, name: &str) -> Option<String> {
+    let tag = &text[text.find(tag)?..];
+    let tag = &tag[..tag.find('>')?];
+    let value = &tag[tag.find(&format!("{}=\"", name))? + name.len() + 2..];
+    Some(value[..value.find('"')?].to_string())
+}
+
+fn find(haystack: &[u8], needle: &[u8], ignore_case: bool) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| {
+        if ignore_case {
+            w.eq_ignore_ascii_case(needle)
+        } else {
+            w == needle
+        }
+    })
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
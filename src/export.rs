@@ -0,0 +1,88 @@
+use crate::bookmarks;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use openai::chat::ChatCompletion;
+use serde_json::json;
+use std::fs::write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Front matter, the prompt and the answer as Markdown sections
+    Md,
+    /// The prompt, the answers and the token usage
+    Json,
+    /// The answer alone
+    Txt,
+}
+
+impl ExportFormat {
+    /// The format a file name asks for: `.md` and `.json`, anything else is plain text.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("md" | "markdown") => ExportFormat::Md,
+            Some("json") => ExportFormat::Json,
+            _ => ExportFormat::Txt,
+        }
+    }
+}
+
+fn answers(completion: &ChatCompletion) -> Vec<&str> {
+    completion
+        .choices
+        .iter()
+        .map(|choice| choice.message.content.as_deref().unwrap_or_default().trim())
+        .collect()
+}
+
+/// The prompt and the completion in `format`.
+pub fn render(prompt: &str, completion: &ChatCompletion, format: ExportFormat) -> Result<String> {
+    let answers = answers(completion);
+    Ok(match format {
+        ExportFormat::Txt => format!("{}\n", answers.join("\n\n")),
+        ExportFormat::Md => {
+            let mut text = format!(
+                "---\nmodel: {}\ndate: {}\n---\n\n## Prompt\n\n{}\n",
+                completion.model,
+                bookmarks::date(completion.created),
+                prompt.trim()
+            );
+            for (i, answer) in answers.iter().enumerate() {
+                match answers.len() {
+                    1 => text.push_str(&format!("\n## Answer\n\n{}\n", answer)),
+                    _ => text.push_str(&format!("\n## Answer {}\n\n{}\n", i + 1, answer)),
+                }
+            }
+            text
+        }
+        ExportFormat::Json => {
+            let value = json!({
+                "id": completion.id,
+                "model": completion.model,
+                "created": completion.created,
+                "prompt": prompt,
+                "answers": completion.choices.iter().zip(&answers).map(|(choice, answer)| json!({
+                    "content": answer,
+                    "finish_reason": choice.finish_reason,
+                })).collect::<Vec<_>>(),
+                "usage": completion.usage.as_ref().map(|usage| json!({
+                    "prompt_tokens": usage.prompt_tokens,
+                    "completion_tokens": usage.completion_tokens,
+                    "total_tokens": usage.total_tokens,
+                })),
+            });
+            format!("{}\n", serde_json::to_string_pretty(&value)?)
+        }
+    })
+}
+
+/// Writes the prompt and the completion to `path`, replacing what was there.
+pub fn save(
+    path: &Path,
+    prompt: &str,
+    completion: &ChatCompletion,
+    format: ExportFormat,
+) -> Result<()> {
+    write(path, render(prompt, completion, format)?)
+        .with_context(|| format!("Can't write {}", path.display()))
+}
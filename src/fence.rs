@@ -0,0 +1,102 @@
+/// Wraps `text` in a fenced code block tagged with its detected language when it
+/// looks like code, so pasted sources stay apart from the instructions around them.
+/// Returns `None` for prose and for text that already contains fences.
+pub fn fence(text: &str) -> Option<String> {
+    let code = text.trim_matches('\n');
+    if code.trim().is_empty() || code.contains("```") {
+        return None;
+    }
+    // A single line is only taken for code when it's unambiguous, like a JSON blob.
+    let language = detect_language(code).filter(|l| code.contains('\n') || *l == "json");
+    if language.is_none() && !looks_like_code(code) {
+        return None;
+    }
+    Some(format!("```{}\n{}\n```", language.unwrap_or(""), code))
+}
+
+/// Best guess at the language of a code snippet, as a fence info string.
+pub fn detect_language(code: &str) -> Option<&'static str> {
+    let trimmed = code.trim();
+    let has = |needle: &str| code.contains(needle);
+    let starts = |prefix: &str| code.lines().any(|l| l.trim_start().starts_with(prefix));
+
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some("json");
+    }
+    if trimmed.starts_with("#!") {
+        let shebang = trimmed.lines().next().unwrap_or_default();
+        return if shebang.contains("python") {
+            Some("python")
+        } else if shebang.contains("node") {
+            Some("javascript")
+        } else if shebang.contains("ruby") {
+            Some("ruby")
+        } else {
+            Some("sh")
+        };
+    }
+    if starts("fn ")
+        || starts("pub fn ")
+        || starts("impl ")
+        || has("#[derive")
+        || starts("use std::")
+    {
+        return Some("rust");
+    }
+    if starts("package ") && starts("func ") {
+        return Some("go");
+    }
+    if starts("#include") {
+        return Some(if has("std::") || has("class ") || has("template<") {
+            "cpp"
+        } else {
+            "c"
+        });
+    }
+    if has("public class ") || has("public static void ") {
+        return Some("java");
+    }
+    if starts("def ") || (starts("import ") && !has(";")) || (starts("from ") && has(" import ")) {
+        return Some("python");
+    }
+    if starts("interface ") || has(": string") || has(": number") {
+        return Some("typescript");
+    }
+    if starts("function ") || starts("const ") || starts("let ") || has("=> {") {
+        return Some("javascript");
+    }
+    if trimmed.starts_with('<') && has("</") {
+        return Some("html");
+    }
+    let upper = trimmed.to_uppercase();
+    if (upper.starts_with("SELECT ") && upper.contains(" FROM "))
+        || upper.starts_with("INSERT INTO ")
+        || upper.starts_with("CREATE TABLE ")
+    {
+        return Some("sql");
+    }
+    None
+}
+
+/// Whether most non-blank lines look like statements rather than sentences.
+fn looks_like_code(code: &str) -> bool {
+    let lines = code
+        .lines()
+        .map(str::trim_end)
+        .filter(|l| !l.trim().is_empty())
+        .collect::<Vec<_>>();
+    if lines.len() < 3 {
+        return false;
+    }
+    let codelike = lines
+        .iter()
+        .filter(|l| {
+            l.ends_with([';', '{', '}', '(', ')', ',', '[', ']'])
+                || l.starts_with([' ', '\t'])
+                || l.trim_start().starts_with("//")
+        })
+        .count();
+    codelike * 2 >= lines.len()
+}
@@ -0,0 +1,41 @@
+use crate::chunk::estimate_tokens;
+use tokio::io::{stdin, AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::time::{timeout_at, Duration, Instant};
+
+/// Lines of stdin as they arrive. The channel closes at the end of input.
+pub fn lines() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel(1024);
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if tx.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Waits for the next line, then gathers what arrives within `window` of it.
+/// A batch is cut short when it reaches `max_tokens`. `None` at the end of input.
+pub async fn next_batch(
+    lines: &mut Receiver<String>,
+    window: Duration,
+    max_tokens: usize,
+) -> Option<Vec<String>> {
+    let first = lines.recv().await?;
+    let deadline = Instant::now() + window;
+    let mut tokens = estimate_tokens(&first);
+    let mut batch = vec![first];
+    while tokens < max_tokens {
+        match timeout_at(deadline, lines.recv()).await {
+            Ok(Some(line)) => {
+                tokens += estimate_tokens(&line);
+                batch.push(line);
+            }
+            Ok(None) | Err(_) => break,
+        }
+    }
+    Some(batch)
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Fixed translations of terms for ftrans, read from a YAML map:
+///
+/// ```yaml
+/// pull request: プルリクエスト
+/// deploy: デプロイ
+/// ```
+///
+/// Only the terms a text contains are added to its prompt, and the translation is
+/// checked for them afterwards. Terms match regardless of case.
+#[derive(Debug, Default, Clone)]
+pub struct Glossary {
+    terms: BTreeMap<String, String>,
+}
+
+impl Glossary {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+        let terms = serde_yaml::from_str::<Option<BTreeMap<String, String>>>(&text)
+            .with_context(|| format!("Invalid glossary {}", path.display()))?
+            .unwrap_or_default();
+        Ok(Self { terms })
+    }
+
+    /// Entries whose term appears in `source`.
+    fn relevant<'a>(&'a self, source: &str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        let source = source.to_lowercase();
+        self.terms
+            .iter()
+            .filter(move |(term, _)| source.contains(&term.to_lowercase()))
+            .map(|(term, translation)| (term.as_str(), translation.as_str()))
+    }
+
+    /// Lines to append to the system prompt for translating `source`, if it has any terms.
+    pub fn instruction(&self, source: &str) -> Option<String> {
+        let lines = self
+            .relevant(source)
+            .map(|(term, translation)| format!("- {} → {}", term, translation))
+            .collect::<Vec<_>>();
+        (!lines.is_empty()).then(|| {
+            format!(
+                "Translate these terms exactly as given:\n{}",
+                lines.join("\n")
+            )
+        })
+    }
+
+    /// `prompt` with the instruction for `source` appended.
+    pub fn apply(&self, prompt: &str, source: &str) -> String {
+        match self.instruction(source) {
+            Some(instruction) => format!("{}\n\n{}", prompt.trim_end(), instruction),
+            None => prompt.to_string(),
+        }
+    }
+
+    /// Terms of `source` whose translation doesn't appear in `translation`.
+    pub fn misses<'a>(&'a self, source: &str, translation: &str) -> Vec<(&'a str, &'a str)> {
+        let translation = translation.to_lowercase();
+        self.relevant(source)
+            .filter(|(_, expected)| !translation.contains(&expected.to_lowercase()))
+            .collect()
+    }
+
+    /// Warns on stderr about the terms that weren't translated as listed.
+    pub fn check(&self, source: &str, translation: &str) {
+        for (term, expected) in self.misses(source, translation) {
+            eprintln!(
+                "Glossary: `{}` should be translated as `{}`",
+                term, expected
+            );
+        }
+    }
+}
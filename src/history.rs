@@ -0,0 +1,88 @@
+use crate::config::data_dir;
+use crate::input::LONG_INPUT_CHARS;
+use anyhow::{Context, Result};
+use std::fs::{create_dir_all, read_to_string, write, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Entries kept when the history file is rewritten.
+const MAX_ENTRIES: usize = 1000;
+
+/// Earlier inputs of the fchat prompt, oldest first, for ↑/↓ and Ctrl-R.
+/// Persisted in `history` in the config directory, one JSON string per line so
+/// multi-line inputs survive. Inputs longer than `LONG_INPUT_CHARS` (pasted files,
+/// logs, anything with secrets in it) are only remembered until fchat exits.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// `None` keeps the history in memory only.
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// The saved history. Lines that don't parse are skipped.
+    pub fn load() -> Result<Self> {
+        let path = data_dir()?.join("history");
+        let entries = match read_to_string(&path) {
+            Ok(text) => text
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Ok(Self {
+            entries,
+            path: Some(path),
+        })
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Remembers an input, unless it's blank or the same as the one before.
+    pub fn add(&mut self, entry: &str) -> Result<()> {
+        if entry.trim().is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+            return Ok(());
+        }
+        self.entries.push(entry.to_string());
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !saved(entry) {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).with_context(|| "Can't create config directory")?;
+        }
+        if self.entries.len() > MAX_ENTRIES * 2 {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+            let lines = self
+                .entries
+                .iter()
+                .filter(|entry| saved(entry))
+                .map(|entry| serde_json::to_string(entry).map(|line| line + "\n"))
+                .collect::<serde_json::Result<String>>()?;
+            return write(path, lines).with_context(|| format!("Can't write {}", path.display()));
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Can't open {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)
+            .with_context(|| format!("Can't write {}", path.display()))
+    }
+
+    /// The newest entry before `before` that contains `query`.
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+}
+
+/// Whether `entry` is short enough to be written to the history file.
+fn saved(entry: &str) -> bool {
+    entry.chars().count() <= LONG_INPUT_CHARS
+}
@@ -0,0 +1,540 @@
+use crate::completion::{common_prefix, Completer};
+use crate::fence::fence;
+use crate::history::History;
+use anyhow::{bail, Result};
+use crossterm::{
+    cursor::{MoveToColumn, MoveUp},
+    event::{
+        self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers,
+    },
+    execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType},
+};
+use std::io::{stdin, stdout, BufRead, IsTerminal, Write};
+use std::time::Duration;
+use unicode_width::UnicodeWidthChar;
+
+/// Inputs longer than this are no longer drawn in full, only as a preview.
+pub const LONG_INPUT_CHARS: usize = 2000;
+
+/// More completion candidates than this are only counted.
+const MAX_CANDIDATES: usize = 100;
+
+/// Reads one line from the terminal.
+///
+/// Unlike `inquire::Text`, pasted text is taken in one piece (bracketed paste) or,
+/// on terminals without it, drawn once per burst of keys instead of once per character,
+/// so pasting a large minified blob doesn't freeze the prompt. Inputs longer than
+/// `LONG_INPUT_CHARS` are shown as a preview while the full text is returned.
+/// With `fence_pastes`, pasted code is wrapped in a fenced block (see `fence::fence`).
+/// ↑/↓ and Ctrl-R bring back earlier inputs from `history`; Emacs keys edit the line
+/// and Tab completes with `completer`.
+pub fn read_line(
+    prompt: &str,
+    fence_pastes: bool,
+    history: &History,
+    completer: &Completer,
+) -> Result<String> {
+    read(prompt, fence_pastes, false, history, completer)
+}
+
+/// Reads multi-line text for drafting: Enter starts a new line and Ctrl-D (or Alt+Enter)
+/// submits. Otherwise behaves like `read_line`.
+pub fn read_compose(
+    prompt: &str,
+    fence_pastes: bool,
+    history: &History,
+    completer: &Completer,
+) -> Result<String> {
+    read(prompt, fence_pastes, true, history, completer)
+}
+
+fn read(
+    prompt: &str,
+    fence_pastes: bool,
+    compose: bool,
+    history: &History,
+    completer: &Completer,
+) -> Result<String> {
+    if !stdin().is_terminal() {
+        let mut line = String::new();
+        if stdin().lock().read_line(&mut line)? == 0 {
+            bail!("Input was closed")
+        }
+        return Ok(line.trim_end_matches(['\n', '\r']).to_string());
+    }
+
+    terminal::enable_raw_mode()?;
+    execute!(stdout(), EnableBracketedPaste)?;
+    let line = edit(prompt, fence_pastes, compose, history, completer);
+    let _ = execute!(stdout(), DisableBracketedPaste);
+    terminal::disable_raw_mode()?;
+    println!();
+    line
+}
+
+fn edit(
+    prompt: &str,
+    fence_pastes: bool,
+    compose: bool,
+    history: &History,
+    completer: &Completer,
+) -> Result<String> {
+    let mut buffer: Vec<char> = Vec::new();
+    let mut cursor = 0;
+    // Rows between the first line of the input and the cursor, for redrawing in compose mode.
+    let mut row = 0;
+    // The history entry shown by ↑/↓; `None` is the input being written, kept in `draft`.
+    let mut recalled: Option<usize> = None;
+    let mut draft: Vec<char> = Vec::new();
+    // Ctrl-R: the query and the entry it found.
+    let mut search: Option<(String, Option<usize>)> = None;
+    // Text removed by Ctrl-K, Ctrl-U, Ctrl-W or Alt-D, put back by Ctrl-Y.
+    let mut killed: Vec<char> = Vec::new();
+    let entries = history.entries();
+
+    let draw = |buffer: &[char],
+                cursor: usize,
+                search: &Option<(String, Option<usize>)>,
+                row: &mut usize|
+     -> Result<()> {
+        let (label, found);
+        let (prompt, buffer, cursor) = match search {
+            Some((query, index)) => {
+                label = format!("(search `{}`): ", query);
+                found = index
+                    .map(|i| entries[i].chars().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                (&label[..], &found[..], found.len())
+            }
+            None => (prompt, buffer, cursor),
+        };
+        if compose {
+            *row = render_lines(prompt, buffer, cursor, *row)?;
+            Ok(())
+        } else {
+            render(prompt, buffer, cursor)
+        }
+    };
+    draw(&buffer, cursor, &search, &mut row)?;
+
+    loop {
+        match event::read()? {
+            Event::Paste(text) if search.is_none() => {
+                let pasted = text.replace("\r\n", "\n").replace('\r', "\n");
+                let pasted = match fence(&pasted).filter(|_| fence_pastes) {
+                    Some(fenced) => format!("\n{}\n", fenced),
+                    None => pasted,
+                };
+                let len = pasted.chars().count();
+                buffer.splice(cursor..cursor, pasted.chars());
+                cursor += len;
+            }
+            Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            }) => {
+                let control = modifiers.contains(KeyModifiers::CONTROL);
+                let alt = modifiers.contains(KeyModifiers::ALT);
+                if let Some((query, found)) = &mut search {
+                    match code {
+                        KeyCode::Char('c') if control => {
+                            bail!("Operation was interrupted by the user")
+                        }
+                        KeyCode::Char('r') if control => {
+                            *found = history
+                                .search(query, found.unwrap_or(entries.len()))
+                                .or(*found);
+                        }
+                        KeyCode::Char('g') if control => search = None,
+                        KeyCode::Esc => search = None,
+                        KeyCode::Char(c) if !control && !alt => {
+                            query.push(c);
+                            *found = history.search(query, found.map_or(entries.len(), |i| i + 1));
+                        }
+                        KeyCode::Backspace => {
+                            query.pop();
+                            *found = history.search(query, entries.len());
+                        }
+                        // Any other key takes the entry found for editing.
+                        _ => {
+                            if let Some(i) = *found {
+                                buffer = entries[i].chars().collect();
+                                cursor = buffer.len();
+                                recalled = Some(i);
+                            }
+                            search = None;
+                        }
+                    }
+                } else {
+                    let submit = match code {
+                        KeyCode::Enter => !compose || alt,
+                        KeyCode::Char('d') => compose && control && !buffer.is_empty(),
+                        _ => false,
+                    };
+                    if submit {
+                        if compose {
+                            draw(&buffer, buffer.len(), &search, &mut row)?;
+                        }
+                        return Ok(buffer.into_iter().collect());
+                    }
+                    let line_start = buffer[..cursor]
+                        .iter()
+                        .rposition(|c| *c == '\n')
+                        .map_or(0, |i| i + 1);
+                    let line_end = buffer[cursor..]
+                        .iter()
+                        .position(|c| *c == '\n')
+                        .map_or(buffer.len(), |i| cursor + i);
+                    match code {
+                        KeyCode::Enter => {
+                            buffer.insert(cursor, '\n');
+                            cursor += 1;
+                        }
+                        KeyCode::Char('c') if control => {
+                            bail!("Operation was interrupted by the user")
+                        }
+                        KeyCode::Char('d') if control && buffer.is_empty() => {
+                            bail!("Operation was canceled by the user")
+                        }
+                        KeyCode::Char('d') if control && cursor < buffer.len() => {
+                            buffer.remove(cursor);
+                        }
+                        KeyCode::Char('r') if control => search = Some((String::new(), None)),
+                        KeyCode::Char('p') if control => {
+                            recall(entries, &mut recalled, &mut draft, &mut buffer, true);
+                            cursor = buffer.len();
+                        }
+                        KeyCode::Char('n') if control => {
+                            recall(entries, &mut recalled, &mut draft, &mut buffer, false);
+                            cursor = buffer.len();
+                        }
+                        KeyCode::Char('a') if control => cursor = line_start,
+                        KeyCode::Char('e') if control => cursor = line_end,
+                        KeyCode::Char('b') if control => cursor = cursor.saturating_sub(1),
+                        KeyCode::Char('f') if control => cursor = (cursor + 1).min(buffer.len()),
+                        KeyCode::Char('h') if control && cursor > 0 => {
+                            cursor -= 1;
+                            buffer.remove(cursor);
+                        }
+                        KeyCode::Char('u') if control => {
+                            killed = buffer.drain(line_start..cursor).collect();
+                            cursor = line_start;
+                        }
+                        KeyCode::Char('k') if control => {
+                            killed = buffer.drain(cursor..line_end).collect();
+                        }
+                        KeyCode::Char('w') if control => {
+                            let start = word_left(&buffer, cursor);
+                            killed = buffer.drain(start..cursor).collect();
+                            cursor = start;
+                        }
+                        KeyCode::Backspace if alt => {
+                            let start = word_left(&buffer, cursor);
+                            killed = buffer.drain(start..cursor).collect();
+                            cursor = start;
+                        }
+                        KeyCode::Char('d') if alt => {
+                            let end = word_right(&buffer, cursor);
+                            killed = buffer.drain(cursor..end).collect();
+                        }
+                        KeyCode::Char('y') if control => {
+                            buffer.splice(cursor..cursor, killed.iter().copied());
+                            cursor += killed.len();
+                        }
+                        KeyCode::Char('b') if alt => cursor = word_left(&buffer, cursor),
+                        KeyCode::Char('f') if alt => cursor = word_right(&buffer, cursor),
+                        KeyCode::Left if control || alt => cursor = word_left(&buffer, cursor),
+                        KeyCode::Right if control || alt => cursor = word_right(&buffer, cursor),
+                        KeyCode::Char(c) if !control && !alt => {
+                            buffer.insert(cursor, c);
+                            cursor += 1;
+                        }
+                        KeyCode::Tab => {
+                            let line = buffer[line_start..cursor].iter().collect::<String>();
+                            let (start, candidates) = completer.complete(&line);
+                            let start = line_start + line[..start].chars().count();
+                            let prefix = common_prefix(&candidates);
+                            let completed = match &candidates[..] {
+                                [] => None,
+                                [only] if only.ends_with('/') => Some(only.clone()),
+                                [only] => Some(format!("{} ", only)),
+                                _ if prefix.chars().count() > cursor - start => {
+                                    Some(prefix.to_string())
+                                }
+                                _ => {
+                                    // Nothing more in common: list the candidates under the input.
+                                    draw(&buffer, buffer.len(), &search, &mut row)?;
+                                    let list = match candidates.len() {
+                                        n if n > MAX_CANDIDATES => format!("{} candidates", n),
+                                        _ => candidates.join("  "),
+                                    };
+                                    execute!(stdout(), Print(format!("\r\n{}\r\n", list)))?;
+                                    row = 0;
+                                    None
+                                }
+                            };
+                            if let Some(completed) = completed {
+                                let len = completed.chars().count();
+                                buffer.splice(start..cursor, completed.chars());
+                                cursor = start + len;
+                            }
+                        }
+                        KeyCode::Backspace if cursor > 0 => {
+                            cursor -= 1;
+                            buffer.remove(cursor);
+                        }
+                        KeyCode::Delete if cursor < buffer.len() => {
+                            buffer.remove(cursor);
+                        }
+                        // In compose mode ↑/↓ move between lines and reach the history
+                        // from the first and the last line.
+                        KeyCode::Up if compose && line_start > 0 => {
+                            cursor = vertical(&buffer, cursor, false)
+                        }
+                        KeyCode::Down if compose && line_end < buffer.len() => {
+                            cursor = vertical(&buffer, cursor, true)
+                        }
+                        KeyCode::Up => {
+                            recall(entries, &mut recalled, &mut draft, &mut buffer, true);
+                            cursor = buffer.len();
+                        }
+                        KeyCode::Down => {
+                            recall(entries, &mut recalled, &mut draft, &mut buffer, false);
+                            cursor = buffer.len();
+                        }
+                        KeyCode::Left => cursor = cursor.saturating_sub(1),
+                        KeyCode::Right => cursor = (cursor + 1).min(buffer.len()),
+                        KeyCode::Home => cursor = line_start,
+                        KeyCode::End => cursor = line_end,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        // Keys of an unbracketed paste arrive in a burst; draw once it's over.
+        if !event::poll(Duration::ZERO)? {
+            draw(&buffer, cursor, &search, &mut row)?;
+        }
+    }
+}
+
+/// Replaces the input with the next older (or newer) history entry. Going past the
+/// newest entry brings back what was being written.
+fn recall(
+    entries: &[String],
+    recalled: &mut Option<usize>,
+    draft: &mut Vec<char>,
+    buffer: &mut Vec<char>,
+    older: bool,
+) {
+    let next = match (*recalled, older) {
+        (None, true) => match entries.len().checked_sub(1) {
+            Some(last) => {
+                *draft = buffer.clone();
+                Some(last)
+            }
+            None => return,
+        },
+        (Some(i), true) => Some(i.saturating_sub(1)),
+        (Some(i), false) if i + 1 < entries.len() => Some(i + 1),
+        (Some(_), false) => None,
+        (None, false) => return,
+    };
+    *buffer = match next {
+        Some(i) => entries[i].chars().collect(),
+        None => std::mem::take(draft),
+    };
+    *recalled = next;
+}
+
+/// Start of the word before the cursor.
+fn word_left(buffer: &[char], cursor: usize) -> usize {
+    let mut i = cursor;
+    while i > 0 && !buffer[i - 1].is_alphanumeric() {
+        i -= 1;
+    }
+    while i > 0 && buffer[i - 1].is_alphanumeric() {
+        i -= 1;
+    }
+    i
+}
+
+/// End of the word after the cursor.
+fn word_right(buffer: &[char], cursor: usize) -> usize {
+    let mut i = cursor;
+    while i < buffer.len() && !buffer[i].is_alphanumeric() {
+        i += 1;
+    }
+    while i < buffer.len() && buffer[i].is_alphanumeric() {
+        i += 1;
+    }
+    i
+}
+
+/// Draws the input on the current line, scrolled horizontally so the cursor stays visible.
+fn render(prompt: &str, buffer: &[char], cursor: usize) -> Result<()> {
+    let width = terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .unwrap_or(80);
+    let available = width.saturating_sub(prompt.chars().count() + 1).max(1);
+    let mut out = stdout();
+
+    if buffer.len() > LONG_INPUT_CHARS {
+        // Only the head is collected so drawing stays cheap however large the input grows.
+        let head = buffer
+            .iter()
+            .take(available.saturating_sub(24).max(8))
+            .copied()
+            .map(display_char)
+            .collect::<String>();
+        queue!(
+            out,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(prompt),
+            Print(format!("{}… ({} chars)", head, buffer.len()))
+        )?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    let mut start = cursor;
+    let mut before = 0;
+    while start > 0 && before + char_width(buffer[start - 1]) < available {
+        start -= 1;
+        before += char_width(buffer[start]);
+    }
+    let mut end = cursor;
+    let mut used = before;
+    while end < buffer.len() && used + char_width(buffer[end]) <= available {
+        used += char_width(buffer[end]);
+        end += 1;
+    }
+    let visible = buffer[start..end]
+        .iter()
+        .copied()
+        .map(display_char)
+        .collect::<String>();
+    queue!(
+        out,
+        MoveToColumn(0),
+        Clear(ClearType::CurrentLine),
+        Print(prompt),
+        Print(visible),
+        MoveToColumn((prompt.chars().count() + before) as u16)
+    )?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Draws the input over as many rows as it needs, continuation lines indented under the
+/// prompt, and returns the row of the cursor. `row` is where the cursor was drawn last time.
+fn render_lines(prompt: &str, buffer: &[char], cursor: usize, row: usize) -> Result<usize> {
+    let width = terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .unwrap_or(80)
+        .max(1);
+    let mut out = stdout();
+    queue!(out, MoveToColumn(0))?;
+    if row > 0 {
+        queue!(out, MoveUp(row as u16))?;
+    }
+    queue!(out, Clear(ClearType::FromCursorDown))?;
+
+    if buffer.len() > LONG_INPUT_CHARS {
+        let head = buffer
+            .iter()
+            .take(width.saturating_sub(prompt.chars().count() + 24).max(8))
+            .copied()
+            .map(display_char)
+            .collect::<String>();
+        queue!(
+            out,
+            Print(prompt),
+            Print(format!("{}… ({} chars)", head, buffer.len()))
+        )?;
+        out.flush()?;
+        return Ok(0);
+    }
+
+    let indent = " ".repeat(prompt.chars().count());
+    let (mut current_row, mut column) = (0, prompt.chars().count());
+    let mut at_cursor = (0, column);
+    let mut text = String::from(prompt);
+    for (i, c) in buffer.iter().enumerate() {
+        if i == cursor {
+            at_cursor = (current_row, column);
+        }
+        if *c == '\n' {
+            text.push_str("\r\n");
+            text.push_str(&indent);
+            current_row += 1;
+            column = indent.len();
+            continue;
+        }
+        let w = char_width(*c);
+        if column + w > width {
+            current_row += 1;
+            column = 0;
+        }
+        text.push(display_char(*c));
+        column += w;
+    }
+    if cursor >= buffer.len() {
+        at_cursor = (current_row, column);
+    }
+    queue!(out, Print(text))?;
+    if current_row > at_cursor.0 {
+        queue!(out, MoveUp((current_row - at_cursor.0) as u16))?;
+    }
+    queue!(out, MoveToColumn(at_cursor.1.min(width - 1) as u16))?;
+    out.flush()?;
+    Ok(at_cursor.0)
+}
+
+/// Cursor position one line up or down, keeping the column where possible.
+fn vertical(buffer: &[char], cursor: usize, down: bool) -> usize {
+    let start = buffer[..cursor]
+        .iter()
+        .rposition(|c| *c == '\n')
+        .map_or(0, |i| i + 1);
+    let column = cursor - start;
+    if down {
+        let Some(end) = buffer[cursor..].iter().position(|c| *c == '\n') else {
+            return buffer.len();
+        };
+        let next = cursor + end + 1;
+        let next_len = buffer[next..]
+            .iter()
+            .position(|c| *c == '\n')
+            .unwrap_or(buffer.len() - next);
+        next + column.min(next_len)
+    } else {
+        if start == 0 {
+            return 0;
+        }
+        let previous = buffer[..start - 1]
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |i| i + 1);
+        previous + column.min(start - 1 - previous)
+    }
+}
+
+fn display_char(c: char) -> char {
+    match c {
+        '\n' => '↵',
+        '\t' => ' ',
+        c => c,
+    }
+}
+
+fn char_width(c: char) -> usize {
+    display_char(c).width().unwrap_or(0)
+}
@@ -0,0 +1,311 @@
+use crate::bookmarks::date;
+use crate::config::data_dir;
+use crate::summary;
+use anyhow::{Context, Result};
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, rename, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One change to the conversation, a line of the journal.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    Push {
+        message: ChatCompletionMessage,
+    },
+    Truncate {
+        len: usize,
+    },
+    /// The conversation continues with another model from here on.
+    Model {
+        model: String,
+    },
+    /// The first `len` messages are the seed (system prompt, template, loaded files)
+    /// that `reset` goes back to.
+    Seed {
+        len: usize,
+    },
+    /// `messages` were replaced by `summary`; the entries after it rewrite the conversation.
+    Compact {
+        summary: String,
+        messages: Vec<ChatCompletionMessage>,
+    },
+}
+
+/// Append-only JSONL record of an fchat conversation, written after every change so
+/// the conversation survives a crash or kill. The file stays locked while fchat runs
+/// and is removed when the conversation ends normally, unless messages were compacted:
+/// then it's moved to `compacted_dir` as the only copy of the replaced messages.
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+    written: Vec<ChatCompletionMessage>,
+    compacted: bool,
+}
+
+pub fn dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("journal"))
+}
+
+/// Where the journals of conversations with compacted messages are kept.
+pub fn compacted_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("compacted"))
+}
+
+impl Journal {
+    pub fn create() -> Result<Self> {
+        let dir = dir()?;
+        create_dir_all(&dir).with_context(|| "Can't create journal directory")?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let path = dir.join(format!("{}-{}.jsonl", started, process::id()));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Can't create {}", path.display()))?;
+        file.try_lock()
+            .with_context(|| format!("Can't lock {}", path.display()))?;
+        Ok(Self {
+            file,
+            path,
+            written: Vec::new(),
+            compacted: false,
+        })
+    }
+
+    /// Appends whatever changed since the last call.
+    pub fn sync(&mut self, messages: &[ChatCompletionMessage]) -> Result<()> {
+        let common = self
+            .written
+            .iter()
+            .zip(messages)
+            .take_while(|(written, message)| written == message)
+            .count();
+        let mut entries = Vec::new();
+        if common < self.written.len() {
+            entries.push(Entry::Truncate { len: common });
+        }
+        entries.extend(messages[common..].iter().map(|message| Entry::Push {
+            message: message.clone(),
+        }));
+        self.write(entries)?;
+        self.written = messages.to_vec();
+        Ok(())
+    }
+
+    /// Records a switch to `model` after the messages written so far.
+    pub fn set_model(&mut self, model: &str) -> Result<()> {
+        self.write(vec![Entry::Model {
+            model: model.to_string(),
+        }])
+    }
+
+    /// Records that the first `len` messages are the seed of the conversation.
+    pub fn set_seed(&mut self, len: usize) -> Result<()> {
+        self.write(vec![Entry::Seed { len }])
+    }
+
+    /// Records that `messages` are about to be replaced by `summary`, keeping the originals.
+    pub fn compact(&mut self, summary: &str, messages: &[ChatCompletionMessage]) -> Result<()> {
+        self.compacted = true;
+        self.write(vec![Entry::Compact {
+            summary: summary.to_string(),
+            messages: messages.to_vec(),
+        }])
+    }
+
+    fn write(&mut self, entries: Vec<Entry>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(&entry)?);
+            lines.push('\n');
+        }
+        self.file
+            .write_all(lines.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .with_context(|| format!("Can't write {}", self.path.display()))
+    }
+
+    /// Continues the conversation of `interrupted`: its compacted messages are carried
+    /// over to this journal and its own journal is removed.
+    pub fn restore(&mut self, interrupted: &Interrupted) -> Result<()> {
+        let entries = interrupted
+            .compactions
+            .iter()
+            .map(|(summary, messages)| Entry::Compact {
+                summary: summary.clone(),
+                messages: messages.clone(),
+            })
+            .collect::<Vec<_>>();
+        self.compacted |= !entries.is_empty();
+        self.write(entries)?;
+        remove_file(&interrupted.path)
+            .with_context(|| format!("Can't remove {}", interrupted.path.display()))
+    }
+
+    /// Removes the journal of a conversation that ended normally, or moves it to
+    /// `compacted_dir` if it holds compacted messages and returns where it went.
+    pub fn finish(self) -> Result<Option<PathBuf>> {
+        if !self.compacted {
+            remove_file(&self.path)
+                .with_context(|| format!("Can't remove {}", self.path.display()))?;
+            return Ok(None);
+        }
+        keep(&self.path).map(Some)
+    }
+}
+
+/// Moves the journal at `path` to `compacted_dir`.
+fn keep(path: &Path) -> Result<PathBuf> {
+    let dir = compacted_dir()?;
+    create_dir_all(&dir).with_context(|| format!("Can't create {}", dir.display()))?;
+    let kept = dir.join(path.file_name().unwrap_or_default());
+    rename(path, &kept)
+        .with_context(|| format!("Can't move {} to {}", path.display(), kept.display()))?;
+    Ok(kept)
+}
+
+/// A conversation whose fchat didn't exit normally.
+pub struct Interrupted {
+    pub path: PathBuf,
+    pub messages: Vec<ChatCompletionMessage>,
+    /// The model switched to last with `/model`.
+    pub model: Option<String>,
+    /// How many of the first messages are the seed.
+    pub seed: usize,
+    /// Unix time in seconds of the last change.
+    pub modified: u64,
+    /// Summaries and the messages they replaced.
+    compactions: Vec<(String, Vec<ChatCompletionMessage>)>,
+}
+
+impl Interrupted {
+    /// Date, number of messages and the beginning of the last one the user typed.
+    pub fn summary(&self) -> String {
+        let last = self
+            .messages
+            .iter()
+            .rfind(|m| m.role == ChatCompletionMessageRole::User)
+            .and_then(|m| m.content.as_deref())
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut preview = last.chars().take(60).collect::<String>();
+        if last.chars().count() > 60 {
+            preview.push('…');
+        }
+        format!(
+            "{} ({} messages) {}",
+            date(self.modified),
+            self.messages.len(),
+            preview
+        )
+    }
+
+    /// Removes the journal, or moves it to `compacted_dir` if it holds compacted messages
+    /// and returns where it went.
+    pub fn discard(&self) -> Result<Option<PathBuf>> {
+        if self.compactions.is_empty() {
+            remove_file(&self.path)
+                .with_context(|| format!("Can't remove {}", self.path.display()))?;
+            return Ok(None);
+        }
+        keep(&self.path).map(Some)
+    }
+}
+
+/// Journals not held by a running fchat that contain something the user typed, newest first.
+/// Journals without any user message are removed.
+pub fn interrupted() -> Result<Vec<Interrupted>> {
+    let dir = dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut found = Vec::new();
+    for entry in read_dir(&dir).with_context(|| "Can't read journal directory")? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+            continue;
+        }
+        // A running fchat holds the lock on its own journal.
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        if file.try_lock().is_err() {
+            continue;
+        }
+        let modified = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let interrupted = replay(path, modified)?;
+        if !interrupted
+            .messages
+            .iter()
+            .any(|m| m.role == ChatCompletionMessageRole::User)
+        {
+            let _ = remove_file(&interrupted.path);
+            continue;
+        }
+        found.push(interrupted);
+    }
+    found.sort_by_key(|interrupted| std::cmp::Reverse(interrupted.modified));
+    Ok(found)
+}
+
+/// Rebuilds the conversation, the last model switched to, the seed and the compactions
+/// from a journal. A line cut short by the crash is ignored. Journals without a seed
+/// entry take the leading system messages for it.
+fn replay(path: PathBuf, modified: u64) -> Result<Interrupted> {
+    let mut messages = Vec::new();
+    let mut model = None;
+    let mut seed = None;
+    let mut compactions = Vec::new();
+    let journal =
+        read_to_string(&path).with_context(|| format!("Can't read {}", path.display()))?;
+    for line in journal.lines() {
+        match serde_json::from_str(line) {
+            Ok(Entry::Push { message }) => messages.push(message),
+            Ok(Entry::Truncate { len }) => messages.truncate(len),
+            Ok(Entry::Model { model: name }) => model = Some(name),
+            Ok(Entry::Seed { len }) => seed = Some(len),
+            Ok(Entry::Compact {
+                summary,
+                messages: replaced,
+            }) => compactions.push((summary, replaced)),
+            Err(_) => break,
+        }
+    }
+    let seed = seed
+        .unwrap_or_else(|| {
+            messages
+                .iter()
+                .take_while(|m| {
+                    m.role == ChatCompletionMessageRole::System && !summary::is_summary(m)
+                })
+                .count()
+        })
+        .min(messages.len());
+    Ok(Interrupted {
+        path,
+        messages,
+        model,
+        seed,
+        modified,
+        compactions,
+    })
+}
@@ -0,0 +1,112 @@
+use crate::config::data_dir;
+use anyhow::{bail, Context, Result};
+use std::fs::{create_dir_all, remove_file, write};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const SERVICE: &str = "FerriteChatter";
+const ACCOUNT: &str = "openai_api_key";
+
+/// Left by `ferrite auth login` so the keyring is only asked when a key was stored
+/// there; running `security` or `secret-tool` on every start can hang or prompt on
+/// machines without a keyring, e.g. in CI.
+fn marker() -> Result<PathBuf> {
+    Ok(data_dir()?.join("keyring"))
+}
+
+/// The API key stored with `ferrite auth login`, if any.
+/// Uses `security` (Keychain) on macOS and `secret-tool` (Secret Service) elsewhere.
+pub fn get() -> Option<String> {
+    if !marker().ok()?.exists() {
+        return None;
+    }
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", SERVICE, "-a", ACCOUNT, "-w"])
+            .stderr(Stdio::null())
+            .output()
+    } else {
+        Command::new("secret-tool")
+            .args(["lookup", "service", SERVICE, "account", ACCOUNT])
+            .stderr(Stdio::null())
+            .output()
+    }
+    .ok()?;
+    let key = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    Some(key).filter(|key| output.status.success() && !key.is_empty())
+}
+
+pub fn set(key: &str) -> Result<()> {
+    let mut child = if cfg!(target_os = "macos") {
+        // Commands read by `security -i` from stdin don't show up in `ps` like arguments do.
+        Command::new("security")
+            .arg("-i")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| "Can't run `security`")?
+    } else {
+        Command::new("secret-tool")
+            .args([
+                "store",
+                "--label=FerriteChatter API key",
+                "service",
+                SERVICE,
+                "account",
+                ACCOUNT,
+            ])
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| "Can't run `secret-tool` (install libsecret-tools)")?
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        if cfg!(target_os = "macos") {
+            writeln!(
+                stdin,
+                "add-generic-password -U -s {} -a {} -w {}",
+                SERVICE,
+                ACCOUNT,
+                quote(key)
+            )?;
+        } else {
+            write!(stdin, "{}", key)?;
+        }
+    }
+    if !child.wait()?.success() {
+        bail!("Can't store the API key in the keyring")
+    }
+    let marker = marker()?;
+    if let Some(dir) = marker.parent() {
+        create_dir_all(dir).with_context(|| "Can't create config directory")?;
+    }
+    write(&marker, "").with_context(|| format!("Can't write {}", marker.display()))
+}
+
+/// `text` as one double-quoted word of a `security -i` command line.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn delete() -> Result<()> {
+    let status = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["delete-generic-password", "-s", SERVICE, "-a", ACCOUNT])
+            .stdout(Stdio::null())
+            .status()
+            .with_context(|| "Can't run `security`")?
+    } else {
+        Command::new("secret-tool")
+            .args(["clear", "service", SERVICE, "account", ACCOUNT])
+            .status()
+            .with_context(|| "Can't run `secret-tool`")?
+    };
+    if !status.success() {
+        bail!("Can't remove the API key from the keyring")
+    }
+    let marker = marker()?;
+    if marker.exists() {
+        remove_file(&marker).with_context(|| format!("Can't remove {}", marker.display()))?;
+    }
+    Ok(())
+}
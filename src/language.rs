@@ -0,0 +1,91 @@
+use anyhow::{bail, Result};
+use std::fmt;
+
+/// Languages ftrans knows by code; `--from`/`--to` also take their names.
+pub const LANGUAGES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("de", "German"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("hi", "Hindi"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("th", "Thai"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
+/// The name of a language given by code or name, in any case.
+pub fn name(language: &str) -> Result<&'static str> {
+    match LANGUAGES.iter().find(|(code, name)| {
+        code.eq_ignore_ascii_case(language) || name.eq_ignore_ascii_case(language)
+    }) {
+        Some((_, name)) => Ok(name),
+        None => bail!(
+            "Unknown language `{}` (--list-languages shows the known ones)",
+            language
+        ),
+    }
+}
+
+/// Which way to translate. Without a target, Japanese becomes English and anything
+/// else becomes Japanese; without a source, it's detected.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Direction {
+    pub from: Option<&'static str>,
+    pub to: Option<&'static str>,
+}
+
+impl Direction {
+    pub fn new(from: Option<&str>, to: Option<&str>) -> Result<Self> {
+        if from.is_some() && to.is_none() {
+            bail!("--from needs --to or `translate_to` in the config")
+        }
+        Ok(Self {
+            from: from.map(name).transpose()?,
+            to: to.map(name).transpose()?,
+        })
+    }
+
+    /// The sentence of a prompt that says which way to translate.
+    pub fn instruction(&self) -> String {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => format!("Translate from {} into {}.", from, to),
+            (None, Some(to)) => format!("Translate into {}, whatever language it is in.", to),
+            _ => String::from("Translate Japanese into English, and anything else into Japanese."),
+        }
+    }
+
+    /// The system prompt for translating plain text.
+    pub fn system_prompt(&self) -> String {
+        match self.to {
+            Some(_) => format!(
+                "Translate the text you receive. {} Answer with the translation only.",
+                self.instruction()
+            ),
+            None => String::from(
+                "これから渡す文章が、日本語の場合は英語に翻訳し、英語など日本語以外の場合は日本語に翻訳してください。",
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => write!(f, "{} → {}", from, to),
+            (None, Some(to)) => write!(f, "→ {}", to),
+            _ => write!(f, "Japanese ⇄ English"),
+        }
+    }
+}
@@ -1,2 +1,90 @@
+//! The chat engine behind `fchat`, `fask`, `ftrans`, `fsh` and `fcommit`.
+//!
+//! [`Chat`] holds a conversation and sends it without printing anything, so it can be
+//! embedded in other programs:
+//!
+//! ```ignore
+//! use FerriteChatter::{Chat, Credentials};
+//!
+//! let mut chat = Chat::new("gpt-4o", Credentials::from_env());
+//! let answer = chat.send("Hello").await?;
+//! ```
+//!
+//! [`cli::bootstrap`] resolves the key, base URL and model the way the binaries do, and
+//! [`document::fetch`] reads web pages. Requests go through a
+//! [`transport::ApiTransport`]; pass another one, e.g. a mock in tests, with
+//! [`core::RequestExtra::transport`]. Modules only the binaries use are hidden from
+//! the documentation and may change without notice.
+
+pub mod cassette;
+pub mod chat;
+pub mod chunk;
+pub mod cli;
 pub mod config;
 pub mod core;
+pub mod document;
+pub mod export;
+pub mod log;
+pub mod retry;
+pub mod templates;
+pub mod transport;
+pub mod workflow;
+
+#[doc(hidden)]
+pub mod bench;
+#[doc(hidden)]
+pub mod bookmarks;
+#[doc(hidden)]
+pub mod commands;
+#[doc(hidden)]
+pub mod comments;
+#[doc(hidden)]
+pub mod completion;
+#[doc(hidden)]
+pub mod diff;
+#[doc(hidden)]
+pub mod fence;
+#[doc(hidden)]
+pub mod follow;
+#[doc(hidden)]
+pub mod glossary;
+#[doc(hidden)]
+pub mod history;
+#[doc(hidden)]
+pub mod input;
+#[doc(hidden)]
+pub mod journal;
+#[doc(hidden)]
+pub mod keyring;
+#[doc(hidden)]
+pub mod language;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod prompt;
+#[doc(hidden)]
+pub mod prompts;
+#[doc(hidden)]
+pub mod render;
+#[doc(hidden)]
+pub mod repetition;
+#[doc(hidden)]
+pub mod review;
+#[doc(hidden)]
+pub mod sink;
+#[doc(hidden)]
+pub mod snippets;
+#[doc(hidden)]
+pub mod spinner;
+#[doc(hidden)]
+pub mod subtitles;
+#[doc(hidden)]
+pub mod summary;
+#[doc(hidden)]
+pub mod terminal;
+#[doc(hidden)]
+pub mod walk;
+
+pub use chat::Chat;
+pub use core::{Model, RequestExtra, Sampling};
+pub use openai::Credentials;
@@ -0,0 +1,161 @@
+use serde_json::{Map, Value};
+use std::env;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+/// Installs the logger for `tracing` events.
+///
+/// Messages go to stderr from the level chosen by `-v` (info) or `-vv` (debug), or by
+/// `FERRITE_LOG` (`error`, `warn`, `info`, `debug`, `trace`) when no flag is given;
+/// only warnings and errors are shown otherwise. With `FERRITE_LOG_FILE`, debug
+/// messages are also appended to that file as JSON lines.
+pub fn init(verbosity: u8) {
+    let stderr = match verbosity {
+        0 => env::var("FERRITE_LOG")
+            .ok()
+            .and_then(|level| parse_level(&level))
+            .unwrap_or(Some(Level::WARN)),
+        1 => Some(Level::INFO),
+        2 => Some(Level::DEBUG),
+        _ => Some(Level::TRACE),
+    };
+    let file = env::var("FERRITE_LOG_FILE")
+        .ok()
+        .and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok());
+    let _ = tracing::subscriber::set_global_default(Logger {
+        stderr,
+        file: file.map(Mutex::new),
+        spans: AtomicU64::new(1),
+    });
+}
+
+/// `None` turns stderr logging off.
+fn parse_level(level: &str) -> Option<Option<Level>> {
+    match level.to_lowercase().as_str() {
+        "off" => Some(None),
+        level => level.parse().ok().map(Some),
+    }
+}
+
+struct Logger {
+    stderr: Option<Level>,
+    file: Option<Mutex<File>>,
+    spans: AtomicU64,
+}
+
+impl Logger {
+    fn file_level(&self) -> Option<Level> {
+        self.file.as_ref().map(|_| Level::DEBUG)
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        // Only this crate's events; dependencies have their own chatter.
+        metadata.target().starts_with("FerriteChatter")
+            && [self.stderr, self.file_level()]
+                .into_iter()
+                .flatten()
+                .any(|level| *metadata.level() <= level)
+    }
+
+    fn new_span(&self, _span: &Attributes<'_>) -> Id {
+        Id::from_u64(self.spans.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        if self.stderr.is_some_and(|level| *metadata.level() <= level) {
+            let mut line = format!("[{}] {}", metadata.level(), fields.message);
+            for (name, value) in &fields.values {
+                match value {
+                    Value::String(s) => line.push_str(&format!(" {}={}", name, s)),
+                    value => line.push_str(&format!(" {}={}", name, value)),
+                }
+            }
+            eprintln!("{}", line);
+        }
+
+        if let Some(file) = self
+            .file
+            .as_ref()
+            .filter(|_| self.file_level().is_some_and(|l| *metadata.level() <= l))
+        {
+            let mut entry = Map::new();
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default();
+            entry.insert(String::from("time"), Value::from(time));
+            entry.insert(
+                String::from("level"),
+                Value::from(metadata.level().as_str()),
+            );
+            entry.insert(String::from("target"), Value::from(metadata.target()));
+            entry.insert(String::from("message"), Value::from(fields.message));
+            entry.extend(fields.values);
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", Value::Object(entry));
+            }
+        }
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    values: Map<String, Value>,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.values
+                .insert(field.name().to_string(), Value::from(value));
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.values
+            .insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.values
+            .insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.values
+            .insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.values
+            .insert(field.name().to_string(), Value::from(value));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::config::{data_dir, Config};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Local-only usage statistics. Nothing here is ever sent over the network;
+/// recording only happens when `metrics: true` is set in the config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Metrics {
+    #[serde(default)]
+    pub commands: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub models: BTreeMap<String, ModelStats>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ModelStats {
+    pub requests: u64,
+    pub total_latency_ms: u64,
+    pub max_latency_ms: u64,
+}
+
+impl ModelStats {
+    pub fn average_latency_ms(&self) -> u64 {
+        self.total_latency_ms
+            .checked_div(self.requests)
+            .unwrap_or_default()
+    }
+}
+
+impl Metrics {
+    pub fn path() -> Result<PathBuf> {
+        Ok(data_dir()?.join("metrics.yaml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            Ok(Self::default())
+        } else {
+            serde_yaml::from_str(&read_to_string(path).with_context(|| "Can't read metrics file")?)
+                .with_context(|| "Can't parse metrics file")
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).with_context(|| "Can't create data directory")?;
+        }
+        write(path, serde_yaml::to_string(self)?).with_context(|| "Can't write metrics file")
+    }
+
+    pub fn report(&self) -> String {
+        let mut lines = vec![String::from("Commands:")];
+        let mut commands = self.commands.iter().collect::<Vec<_>>();
+        commands.sort_by(|a, b| b.1.cmp(a.1));
+        for (command, count) in commands {
+            lines.push(format!("  {:<20} {:>8}", command, count));
+        }
+        lines.push(String::from("Models:"));
+        lines.push(format!(
+            "  {:<28} {:>8} {:>10} {:>10}",
+            "model", "requests", "avg(ms)", "max(ms)"
+        ));
+        for (model, stats) in &self.models {
+            lines.push(format!(
+                "  {:<28} {:>8} {:>10} {:>10}",
+                model,
+                stats.requests,
+                stats.average_latency_ms(),
+                stats.max_latency_ms
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Counts a use of `command`. Metrics are best-effort: a failure is only reported on
+/// stderr so it never stops the program.
+pub fn record_command(config: &Config, command: &str) {
+    update(config, |metrics| {
+        *metrics.commands.entry(command.to_string()).or_default() += 1;
+    });
+}
+
+/// Adds a request to `model` that took `latency`, best-effort like `record_command`.
+pub fn record_request(config: &Config, model: &str, latency: Duration) {
+    update(config, |metrics| {
+        let stats = metrics.models.entry(model.to_string()).or_default();
+        let latency = latency.as_millis() as u64;
+        stats.requests += 1;
+        stats.total_latency_ms += latency;
+        stats.max_latency_ms = stats.max_latency_ms.max(latency);
+    });
+}
+
+fn update(config: &Config, change: impl FnOnce(&mut Metrics)) {
+    if !config.metrics_enabled() {
+        return;
+    }
+    let result = Metrics::load().and_then(|mut metrics| {
+        change(&mut metrics);
+        metrics.save()
+    });
+    if let Err(e) = result {
+        eprintln!("Can't record metrics: {:#}", e);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::chunk::estimate_tokens;
+use crate::{document, fence};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use inquire::Confirm;
+use std::io::{stderr, stdin, IsTerminal, Read};
+use std::path::Path;
+
+/// Where piped stdin goes relative to the prompt argument.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StdinPosition {
+    /// Stdin first, then the argument (e.g. `cat log | fask "why does this fail?"`)
+    #[default]
+    Before,
+    /// The argument first, then stdin
+    After,
+    /// Never read stdin
+    Ignore,
+}
+
+/// Reads stdin when it's piped and not ignored. Blank input counts as no input.
+pub fn read_stdin(position: StdinPosition) -> Option<String> {
+    let mut stdin = stdin();
+    if position == StdinPosition::Ignore || stdin.is_terminal() {
+        return None;
+    }
+    let mut input = String::new();
+    let _ = stdin.read_to_string(&mut input);
+    Some(input).filter(|input| !input.trim().is_empty())
+}
+
+/// Joins piped input and the prompt argument the same way in every binary.
+pub fn compose(
+    stdin: Option<String>,
+    argument: Option<String>,
+    position: StdinPosition,
+) -> Result<String> {
+    match (stdin, argument) {
+        (Some(stdin), Some(argument)) => Ok(match position {
+            StdinPosition::After => format!("{}\n{}", argument, stdin),
+            _ => format!("{}\n{}", stdin, argument),
+        }),
+        (Some(input), None) | (None, Some(input)) => Ok(input),
+        (None, None) => bail!("Please provide input via a pipe or pass the prompt as an argument."),
+    }
+}
+
+/// Guards against `cat hugefile | fask` accidents: input larger than `limit` bytes is
+/// only sent after confirmation on a terminal, or with `force` otherwise.
+/// `price` is in USD per million input tokens.
+pub fn confirm_large_input(
+    input: &str,
+    limit: usize,
+    force: bool,
+    price: Option<f64>,
+) -> Result<()> {
+    if force || input.len() <= limit {
+        return Ok(());
+    }
+    let tokens = estimate_tokens(input);
+    let estimate = match price {
+        Some(price) => format!(
+            "~{} tokens, ~${:.2}",
+            tokens,
+            tokens as f64 / 1_000_000.0 * price
+        ),
+        None => format!("~{} tokens", tokens),
+    };
+    let message = format!("The input is {} KB ({}).", input.len() / 1024, estimate);
+    if !stderr().is_terminal() {
+        bail!("{} Pass --force-large-input to send it anyway.", message)
+    }
+    if !Confirm::new(&format!("{} Send it?", message))
+        .with_default(false)
+        .prompt()?
+    {
+        bail!("Operation was canceled by the user")
+    }
+    Ok(())
+}
+
+/// Expands `@path` references to existing files: the reference becomes the plain path
+/// and the file follows the prompt as a labelled, fenced block. Fails when the files
+/// together exceed `max_tokens`. Words like `@someone` that aren't files stay as they are.
+pub fn expand_references(text: &str, max_tokens: usize) -> Result<String> {
+    let mut prompt = String::new();
+    let mut attached: Vec<String> = Vec::new();
+    let mut blocks = String::new();
+    let mut rest = text;
+    while let Some(at) = rest.find('@') {
+        let (before, after) = rest.split_at(at);
+        prompt.push_str(before);
+        let word_start = before.is_empty() || before.ends_with(char::is_whitespace);
+        let word = after[1..]
+            .split(char::is_whitespace)
+            .next()
+            .unwrap_or_default();
+        // Punctuation closing a sentence isn't part of the path.
+        let path = word.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'']);
+        if !word_start || path.is_empty() || !Path::new(path).is_file() {
+            prompt.push('@');
+            rest = &after[1..];
+            continue;
+        }
+        prompt.push_str(path);
+        rest = &after[1 + path.len()..];
+        if attached.iter().any(|a| a == path) {
+            continue;
+        }
+        let content = document::read_text(Path::new(path))?;
+        let language = fence::detect_language(&content).unwrap_or_default();
+        blocks.push_str(&format!(
+            "\n\n{}:\n```{}\n{}\n```",
+            path,
+            language,
+            content.trim_end()
+        ));
+        attached.push(path.to_string());
+    }
+    prompt.push_str(rest);
+
+    let tokens = estimate_tokens(&blocks);
+    if tokens > max_tokens {
+        bail!(
+            "The referenced files ({}) are ~{} tokens, more than the {} token limit.",
+            attached.join(", "),
+            tokens,
+            max_tokens
+        );
+    }
+    Ok(prompt + &blocks)
+}
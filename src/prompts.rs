@@ -0,0 +1,113 @@
+use crate::config::data_dir;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{copy, create_dir_all, read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+/// An entry of a prompt catalog. Catalogs are YAML lists of these, shared as files
+/// and imported with `ferrite prompts import`:
+///
+/// ```yaml
+/// - name: explain-regex
+///   description: Explain a regular expression step by step
+///   prompt: "Explain this regular expression: {{regex}}"
+///   variables: [regex]
+///   tags: [dev]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub prompt: String,
+    /// Placeholders written as `{{name}}` in the prompt, asked for when it's used.
+    #[serde(default)]
+    pub variables: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Prompt {
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [&self.name, &self.prompt]
+            .into_iter()
+            .chain(self.description.as_ref())
+            .chain(&self.tags)
+            .any(|text| text.to_lowercase().contains(&query))
+    }
+
+    /// The prompt with its variables replaced by `values`, in the order of `variables`.
+    pub fn fill(&self, values: &[String]) -> String {
+        self.variables
+            .iter()
+            .zip(values)
+            .fold(self.prompt.clone(), |prompt, (name, value)| {
+                prompt.replace(&format!("{{{{{}}}}}", name), value)
+            })
+    }
+}
+
+impl fmt::Display for Prompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(description) = &self.description {
+            write!(f, " - {}", description)?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, " [{}]", self.tags.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("prompts"))
+}
+
+fn parse(path: &Path) -> Result<Vec<Prompt>> {
+    let text = read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+    serde_yaml::from_str(&text)
+        .with_context(|| format!("Invalid prompt catalog {}", path.display()))
+}
+
+/// Checks a catalog and copies it into the prompts directory. Returns the number of prompts.
+pub fn import(path: &Path) -> Result<usize> {
+    if !is_catalog(path) {
+        bail!("Prompt catalogs are .yaml files: {}", path.display())
+    }
+    let prompts = parse(path)?;
+    let Some(name) = path.file_name() else {
+        bail!("Invalid catalog path: {}", path.display())
+    };
+    create_dir_all(dir()?).with_context(|| "Can't create prompts directory")?;
+    copy(path, dir()?.join(name)).with_context(|| format!("Can't import {}", path.display()))?;
+    Ok(prompts.len())
+}
+
+/// The prompts of every imported catalog, by catalog file name.
+pub fn load() -> Result<Vec<Prompt>> {
+    let dir = dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = read_dir(dir)
+        .with_context(|| "Can't read prompts directory")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_catalog(path))
+        .collect::<Vec<_>>();
+    paths.sort();
+    let mut prompts = Vec::new();
+    for path in paths {
+        prompts.extend(parse(&path)?);
+    }
+    Ok(prompts)
+}
+
+fn is_catalog(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "yaml" || extension == "yml")
+}
@@ -0,0 +1,256 @@
+use crate::core::{ask, Stream};
+use crate::repetition::{self, Repetition};
+use crate::spinner::Spinner;
+use crate::terminal::{hyperlinks, link_end, link_start};
+use anyhow::Result;
+use crossterm::terminal;
+use openai::chat::ChatCompletion;
+use std::io::{stdout, IsTerminal, Write};
+use std::ops::ControlFlow;
+use unicode_width::UnicodeWidthChar;
+
+const TAB_WIDTH: usize = 8;
+
+/// How much of a line is kept to recognize list markers.
+const PREFIX_CHARS: usize = 16;
+
+const SCHEMES: [&str; 2] = ["https://", "http://"];
+
+/// Soft-wraps streamed text at the terminal width.
+/// East Asian wide characters count as two columns and are moved to the next
+/// line as a whole instead of being split at the right edge.
+/// Wrapped list items and quotes continue under their text rather than at the left edge,
+/// so numbering and nesting stay readable however the deltas are split.
+/// URLs become OSC 8 hyperlinks in terminals that support them; a word that may be
+/// a URL is held back until it's complete, so call `finish` at the end of the answer.
+pub struct Wrapper {
+    enabled: bool,
+    links: bool,
+    column: usize,
+    /// The beginning of the current line.
+    prefix: String,
+    /// A word that may be a URL.
+    pending: String,
+    last: Option<char>,
+}
+
+impl Default for Wrapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Wrapper {
+    pub fn new() -> Self {
+        let enabled = stdout().is_terminal();
+        Self {
+            enabled,
+            links: enabled && hyperlinks(),
+            column: 0,
+            prefix: String::new(),
+            pending: String::new(),
+            last: None,
+        }
+    }
+
+    pub fn wrap(&mut self, text: &str) -> String {
+        // The width is read for every chunk so that resizing the pane mid-answer is picked up.
+        let Some(width) = self.width() else {
+            return self.track(text);
+        };
+
+        let mut wrapped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if !self.links || (self.pending.is_empty() && !(c == 'h' && self.word_start())) {
+                self.put(c, width, &mut wrapped);
+                continue;
+            }
+            self.pending.push(c);
+            if SCHEMES
+                .iter()
+                .any(|scheme| scheme.starts_with(&self.pending))
+            {
+                continue;
+            }
+            let url = SCHEMES
+                .iter()
+                .any(|scheme| self.pending.starts_with(scheme));
+            if url && !ends_url(c) {
+                continue;
+            }
+            let mut word = std::mem::take(&mut self.pending);
+            if url {
+                word.pop();
+                self.put_link(&word, width, &mut wrapped);
+                self.put(c, width, &mut wrapped);
+            } else {
+                for c in word.chars() {
+                    self.put(c, width, &mut wrapped);
+                }
+            }
+        }
+        wrapped
+    }
+
+    /// Writes out a word held back as a possible URL.
+    pub fn finish(&mut self) -> String {
+        let word = std::mem::take(&mut self.pending);
+        let mut wrapped = String::new();
+        match self.width() {
+            Some(width) if SCHEMES.iter().any(|scheme| word.starts_with(scheme)) => {
+                self.put_link(&word, width, &mut wrapped)
+            }
+            Some(width) => {
+                for c in word.chars() {
+                    self.put(c, width, &mut wrapped);
+                }
+            }
+            None => wrapped = self.track(&word),
+        }
+        wrapped
+    }
+
+    fn width(&self) -> Option<usize> {
+        match terminal::size() {
+            Ok((columns, _)) if self.enabled && columns > 0 => Some(columns as usize),
+            _ => None,
+        }
+    }
+
+    fn put(&mut self, c: char, width: usize, wrapped: &mut String) {
+        let w = self.char_width(c);
+        if c != '\n' && self.column + w > width {
+            let hang = marker_width(&self.prefix).filter(|hang| hang * 2 < width);
+            wrapped.push('\n');
+            self.column = 0;
+            if let Some(hang) = hang {
+                wrapped.push_str(&" ".repeat(hang));
+                self.column = hang;
+            }
+        }
+        wrapped.push(c);
+        self.advance(c, w);
+    }
+
+    /// Sentence punctuation after a URL, and a closing parenthesis it didn't open,
+    /// stay outside the link.
+    fn put_link(&mut self, url: &str, width: usize, wrapped: &mut String) {
+        let target = url
+            .trim_end_matches(|c: char| ".,;:!?".contains(c) || (c == ')' && !url.contains('(')));
+        wrapped.push_str(&link_start(target));
+        for c in target.chars() {
+            self.put(c, width, wrapped);
+        }
+        wrapped.push_str(link_end());
+        for c in url[target.len()..].chars() {
+            self.put(c, width, wrapped);
+        }
+    }
+
+    /// Whether the next character starts a word, possibly after markdown punctuation.
+    fn word_start(&self) -> bool {
+        self.last
+            .is_none_or(|c| c.is_whitespace() || "([<\"'`*_".contains(c))
+    }
+
+    fn track(&mut self, text: &str) -> String {
+        for c in text.chars() {
+            let w = self.char_width(c);
+            self.advance(c, w);
+        }
+        text.to_string()
+    }
+
+    fn char_width(&self, c: char) -> usize {
+        match c {
+            '\t' => TAB_WIDTH - self.column % TAB_WIDTH,
+            '\n' | '\r' => 0,
+            _ => c.width().unwrap_or(0),
+        }
+    }
+
+    fn advance(&mut self, c: char, width: usize) {
+        self.last = Some(c);
+        match c {
+            '\n' | '\r' => {
+                self.column = 0;
+                self.prefix.clear();
+            }
+            _ => {
+                self.column += width;
+                if self.prefix.chars().count() < PREFIX_CHARS {
+                    self.prefix.push(c);
+                }
+            }
+        }
+    }
+}
+
+/// Prints a streamed answer to stdout, soft-wrapped by `Wrapper`, and offers to cut it
+/// off when it's caught in a loop (see `Repetition`). Pass `print` as the callback of
+/// `core::ask` and call `finish` once the answer is complete.
+#[derive(Default)]
+pub struct Printer {
+    wrapper: Wrapper,
+    repetition: Repetition,
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn print(&mut self, text: &str) -> ControlFlow<()> {
+        print!("{}", self.wrapper.wrap(text));
+        let _ = stdout().flush();
+        if self.repetition.push(text) && repetition::stop() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        println!("{}", self.wrapper.finish());
+        stdout().flush()?;
+        Ok(())
+    }
+}
+
+/// Streams the first answer to stdout, with a spinner until it starts, and returns the
+/// whole completion, including the other answers when `n > 1` was requested.
+pub async fn print_answer(stream: Stream) -> Result<ChatCompletion> {
+    let mut spinner = Spinner::start();
+    let mut printer = Printer::new();
+    let completion = ask(stream, |text| {
+        spinner.stop();
+        printer.print(text)
+    })
+    .await;
+    spinner.stop();
+    // End what was printed of a cut-off answer too, so the error starts on its own line.
+    printer.finish()?;
+    completion
+}
+
+fn ends_url(c: char) -> bool {
+    c.is_whitespace() || "<>\"'`".contains(c)
+}
+
+/// Width of a leading markdown list marker or quote (`- `, `12. `, `> `, with indentation).
+fn marker_width(line: &str) -> Option<usize> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let rest = &line[indent..];
+    let marker = if rest.starts_with(['-', '*', '+', '>']) {
+        1
+    } else {
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        if (1..=9).contains(&digits) && rest[digits..].starts_with(['.', ')']) {
+            digits + 1
+        } else {
+            return None;
+        }
+    };
+    let spaces = rest[marker..].len() - rest[marker..].trim_start_matches(' ').len();
+    (spaces > 0).then_some(indent + marker + spaces)
+}
@@ -0,0 +1,92 @@
+use inquire::Confirm;
+use std::io::{stdin, stdout, IsTerminal};
+
+/// Longest repeated unit that is looked for, in characters.
+const MAX_PERIOD: usize = 200;
+
+/// The repeated run has to cover at least this many characters...
+const MIN_SPAN: usize = 400;
+
+/// ...and at least this many copies of the unit.
+const MIN_REPEATS: usize = 5;
+
+/// Watches streamed text for a degenerate loop: the end of the answer being the same
+/// run of characters over and over, as happens with extreme temperatures or small models.
+/// Works on characters rather than words so that text without spaces is covered too.
+#[derive(Debug, Default)]
+pub struct Repetition {
+    tail: Vec<char>,
+    reported: bool,
+}
+
+impl Repetition {
+    /// Adds streamed text and tells whether the answer started looping.
+    /// Reports a loop only once.
+    pub fn push(&mut self, text: &str) -> bool {
+        self.tail.extend(text.chars());
+        let keep = MAX_PERIOD * MIN_REPEATS + MIN_SPAN;
+        if self.tail.len() > keep * 2 {
+            self.tail.drain(..self.tail.len() - keep);
+        }
+        if self.reported || !(1..=MAX_PERIOD).any(|period| self.repeats(period)) {
+            return false;
+        }
+        self.reported = true;
+        true
+    }
+
+    /// Whether the tail ends with a unit of `period` characters repeated long enough.
+    fn repeats(&self, period: usize) -> bool {
+        let span = MIN_SPAN.max(period * MIN_REPEATS);
+        if self.tail.len() < span {
+            return false;
+        }
+        let tail = &self.tail[self.tail.len() - span..];
+        // Runs without letters or digits are layout, not a loop: trailing newlines,
+        // `=====` rules or the separator line of a wide Markdown table.
+        tail[..period].iter().any(|c| c.is_alphanumeric())
+            && (period..span).all(|i| tail[i] == tail[i - period])
+    }
+}
+
+/// Asks whether to stop a looping answer, or decides to stop when nobody can be asked.
+pub fn stop() -> bool {
+    println!();
+    if stdin().is_terminal() && stdout().is_terminal() {
+        Confirm::new("The answer keeps repeating itself. Stop it?")
+            .with_default(true)
+            .prompt()
+            .unwrap_or(true)
+    } else {
+        eprintln!("Stopped the answer because it kept repeating itself.");
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looping_text_is_reported_once() {
+        let mut repetition = Repetition::default();
+        assert!(!repetition.push("Here is the answer. "));
+        assert!(repetition.push(&"and again ".repeat(50)));
+        assert!(!repetition.push(&"and again ".repeat(50)));
+    }
+
+    #[test]
+    fn table_separators_and_rules_are_not_loops() {
+        let mut repetition = Repetition::default();
+        let columns = 120;
+        let header = (0..columns)
+            .map(|column| format!(" c{} |", column))
+            .collect::<String>();
+        let header = format!("|{}\n", header);
+        let separator = format!("|{}\n", "------|".repeat(columns));
+        assert!(!repetition.push(&header));
+        assert!(!repetition.push(&separator));
+        assert!(!repetition.push(&"=".repeat(500)));
+        assert!(!repetition.push(&"─".repeat(500)));
+    }
+}
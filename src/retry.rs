@@ -0,0 +1,124 @@
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::io::{stderr, IsTerminal, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Exponential backoff with jitter for rate limits and transient failures.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt. `attempt` starts at 1.
+    /// A `Retry-After` hint from the server takes precedence over the backoff.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        backoff + jitter(backoff / 2)
+    }
+}
+
+pub fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Providers report overload with various status codes (503, 529, even 400), but the
+/// `type` or `code` of the error in the body consistently names it, e.g.
+/// `{"error": {"type": "overloaded_error"}}`. Other text in the body doesn't count.
+pub fn is_overloaded(body: &str) -> bool {
+    let Ok(body) = serde_json::from_str::<Value>(body) else {
+        return false;
+    };
+    ["type", "code"]
+        .iter()
+        .filter_map(|field| body["error"][field].as_str())
+        .map(str::to_lowercase)
+        .any(|kind| kind.contains("overloaded") || kind == "server_error")
+}
+
+/// Short description of a failed attempt for the status line.
+pub fn describe(status: StatusCode, body: &str) -> String {
+    if is_overloaded(body) {
+        String::from("Model overloaded")
+    } else if status == StatusCode::TOO_MANY_REQUESTS {
+        String::from("Rate limited")
+    } else {
+        format!("API returned {}", status.as_u16())
+    }
+}
+
+/// Waits for `delay` while counting down on a single stderr line, e.g.
+/// `Model overloaded, retrying in 3s (attempt 2/5)…`. The line is cleared afterwards.
+pub async fn wait(reason: &str, delay: Duration, attempt: u32, max_attempts: u32) {
+    if !stderr().is_terminal() {
+        eprintln!(
+            "{}, retrying in {}s (attempt {}/{})",
+            reason,
+            delay.as_secs_f64().ceil(),
+            attempt,
+            max_attempts
+        );
+        tokio::time::sleep(delay).await;
+        return;
+    }
+    let mut remaining = delay;
+    while !remaining.is_zero() {
+        eprint!(
+            "\r\x1b[2K{}, retrying in {}s (attempt {}/{})…",
+            reason,
+            remaining.as_secs_f64().ceil(),
+            attempt,
+            max_attempts
+        );
+        let _ = stderr().flush();
+        let step = remaining.min(Duration::from_secs(1));
+        tokio::time::sleep(step).await;
+        remaining -= step;
+    }
+    eprint!("\r\x1b[2K");
+    let _ = stderr().flush();
+}
+
+/// Reads `retry-after-ms` or `retry-after` (in seconds) from the response headers.
+/// A value too large for a `Duration` becomes `Duration::MAX`, which `RetryPolicy::delay`
+/// caps at the maximum delay.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|v| v.is_finite() && *v >= 0.0)
+            .map(|v| Duration::try_from_secs_f64(v).unwrap_or(Duration::MAX))
+    };
+    header("retry-after-ms")
+        .map(|ms| ms / 1000)
+        .or_else(|| header("retry-after"))
+}
+
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    max.mul_f64(f64::from(nanos % 1000) / 1000.0)
+}
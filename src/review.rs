@@ -0,0 +1,130 @@
+use crate::chunk::{estimate_tokens, Chunker};
+use crate::diff;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+
+pub const REVIEW_PROMPT: &str = r#"
+You are a meticulous code reviewer.
+The user sends a diff or a source file where every line is prefixed with its line number.
+Report bugs, security problems, and clear maintainability issues; skip style nitpicks and praise.
+Answer with a JSON object of the form {"findings":[{"path":"<file>","line":<line number>,"severity":"error"|"warning"|"note","message":"<comment>"}]}.
+Use the line numbers shown in the input. Answer {"findings":[]} if there is nothing to report.
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Finding {
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The shape the model is asked to answer in.
+#[derive(Debug, Deserialize)]
+pub struct Findings {
+    #[serde(default)]
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReviewFormat {
+    Text,
+    Sarif,
+}
+
+/// Splits the input into requests of at most `max_tokens`. Diffs are cut between
+/// hunks; anything else is treated as the source of `path` and cut between lines.
+pub fn batches(input: &str, path: &str, max_tokens: usize) -> Vec<String> {
+    let hunks = diff::hunks(input);
+    if hunks.is_empty() {
+        return Chunker::new(max_tokens, 0)
+            .split(input)
+            .into_iter()
+            .map(|chunk| format!("{}\n{}", path, number_lines(&chunk.text, chunk.start_line)))
+            .collect();
+    }
+
+    let mut batches: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for hunk in hunks {
+        let text = format!("{}\n{}", hunk.path, hunk.numbered());
+        if !current.is_empty() && estimate_tokens(&current) + estimate_tokens(&text) > max_tokens {
+            batches.push(std::mem::take(&mut current));
+        }
+        current.push_str(&text);
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+pub fn number_lines(text: &str, start_line: usize) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:>6} {}\n", start_line + i, line))
+        .collect()
+}
+
+pub fn text(findings: &[Finding]) -> String {
+    findings
+        .iter()
+        .map(|f| format!("{}:{}: {}: {}", f.path, f.line, f.severity, f.message))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// SARIF 2.1.0 log with one run, for code scanning dashboards and other tooling.
+pub fn sarif(findings: &[Finding]) -> Value {
+    let results = findings
+        .iter()
+        .map(|f| {
+            json!({
+                "level": f.severity.to_string(),
+                "message": { "text": f.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": f.path },
+                        "region": { "startLine": f.line.max(1) }
+                    }
+                }]
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "FerriteChatter",
+                    "informationUri": "https://github.com/n01e0/FerriteChatter",
+                    "version": env!("CARGO_PKG_VERSION")
+                }
+            },
+            "results": results
+        }]
+    })
+}
@@ -0,0 +1,128 @@
+use crate::bookmarks;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where an answer is sent after it's printed, configured per persona under `outputs`:
+///
+/// ```yaml
+/// outputs:
+///   commit: [clipboard]
+///   notes:
+///     - file: ~/notes/{date}.md
+///     - command: notify-send "fchat" "Saved to notes"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged, expecting = "clipboard, file: <path> or command: <command>")]
+pub enum Sink {
+    /// `pbcopy` on macOS, `wl-copy`, `xclip` or `xsel` elsewhere.
+    Clipboard(Clipboard),
+    /// Appends the answer; `~/` and `{date}` (YYYY-MM-DD) are expanded.
+    File { file: String },
+    /// A shell command that gets the answer on stdin.
+    Command { command: String },
+}
+
+/// The bare `clipboard` keyword.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Clipboard {
+    Clipboard,
+}
+
+impl Sink {
+    pub fn write(&self, answer: &str) -> Result<()> {
+        match self {
+            Sink::Clipboard(_) => copy(answer),
+            Sink::File { file: path } => {
+                let path = expand(path)?;
+                if let Some(dir) = path.parent() {
+                    create_dir_all(dir)?;
+                }
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("Can't open {}", path.display()))?;
+                writeln!(file, "{}\n", answer.trim_end())
+                    .with_context(|| format!("Can't write {}", path.display()))
+            }
+            Sink::Command { command } => {
+                let status = pipe(Command::new("sh").args(["-c", command]), answer)
+                    .with_context(|| format!("Can't run `{}`", command))?;
+                if !status {
+                    bail!("`{}` failed", command)
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sink::Clipboard(_) => write!(f, "the clipboard"),
+            Sink::File { file } => write!(f, "{}", file),
+            Sink::Command { command } => write!(f, "`{}`", command),
+        }
+    }
+}
+
+/// Sends the answer to every sink, reporting failures without stopping.
+pub fn deliver(sinks: &[Sink], answer: &str) {
+    for sink in sinks {
+        if let Err(e) = sink.write(answer) {
+            eprintln!("Output to {} failed: {:#}", sink, e);
+        }
+    }
+}
+
+fn copy(text: &str) -> Result<()> {
+    let candidates: &[&[&str]] = if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else {
+        &[
+            &["wl-copy"],
+            &["xclip", "-selection", "clipboard"],
+            &["xsel", "--clipboard", "--input"],
+        ]
+    };
+    for candidate in candidates {
+        if let Ok(status) = pipe(Command::new(candidate[0]).args(&candidate[1..]), text) {
+            if status {
+                return Ok(());
+            }
+        }
+    }
+    bail!("Can't copy to the clipboard (install wl-clipboard, xclip or xsel)")
+}
+
+/// Runs `command` with `input` on stdin and reports whether it succeeded.
+fn pipe(command: &mut Command, input: &str) -> Result<bool> {
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    Ok(child.wait()?.success())
+}
+
+fn expand(path: &str) -> Result<PathBuf> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let path = path.replace("{date}", &bookmarks::date(secs)[..10]);
+    Ok(match path.strip_prefix("~/") {
+        Some(rest) => {
+            PathBuf::from(env::var("HOME").with_context(|| "Where is the HOME?")?).join(rest)
+        }
+        None => PathBuf::from(path),
+    })
+}
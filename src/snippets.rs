@@ -0,0 +1,55 @@
+use crate::config::data_dir;
+use anyhow::{bail, Context, Result};
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, write};
+use std::path::PathBuf;
+
+/// Saved text blocks (boilerplate instructions, style guides, ...) that can be
+/// inserted into prompts. Each snippet is a plain file named after it.
+pub fn dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("snippets"))
+}
+
+fn path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        bail!("Invalid snippet name: {}", name)
+    }
+    Ok(dir()?.join(name))
+}
+
+pub fn load(name: &str) -> Result<String> {
+    let path = path(name)?;
+    if !path.is_file() {
+        bail!("Unknown snippet: {}", name)
+    }
+    read_to_string(path).with_context(|| format!("Can't read snippet {}", name))
+}
+
+pub fn save(name: &str, text: &str) -> Result<()> {
+    let path = path(name)?;
+    create_dir_all(dir()?).with_context(|| "Can't create snippets directory")?;
+    write(path, text).with_context(|| format!("Can't write snippet {}", name))
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    let path = path(name)?;
+    if !path.is_file() {
+        bail!("Unknown snippet: {}", name)
+    }
+    remove_file(path).with_context(|| format!("Can't remove snippet {}", name))
+}
+
+pub fn list() -> Result<Vec<String>> {
+    let dir = dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = read_dir(dir)
+        .with_context(|| "Can't read snippets directory")?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
@@ -0,0 +1,42 @@
+use std::io::{stderr, IsTerminal, Write};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// Spinner drawn on stderr while waiting for the first token.
+/// Does nothing when stderr isn't a terminal.
+pub struct Spinner {
+    task: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    pub fn start() -> Self {
+        if !stderr().is_terminal() {
+            return Self { task: None };
+        }
+        let task = tokio::spawn(async {
+            let mut interval = tokio::time::interval(Duration::from_millis(80));
+            for frame in FRAMES.iter().cycle() {
+                interval.tick().await;
+                eprint!("\r{}", frame);
+                let _ = stderr().flush();
+            }
+        });
+        Self { task: Some(task) }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            eprint!("\r \r");
+            let _ = stderr().flush();
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
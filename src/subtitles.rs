@@ -0,0 +1,78 @@
+/// A subtitle file (SRT or WebVTT) as blocks separated by blank lines. A block with a
+/// timing line (`00:00:01,000 --> 00:00:02,000`) is a cue: the lines up to and including
+/// the timing line are kept as they are and only the text after it is translated.
+/// Other blocks (the `WEBVTT` header, `NOTE`, `STYLE`) are kept whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subtitles {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    head: Vec<String>,
+    /// `None` for blocks that aren't cues.
+    text: Option<String>,
+}
+
+impl Subtitles {
+    pub fn parse(source: &str) -> Self {
+        let source = source.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        let blocks = source
+            .split("\n\n")
+            .map(|block| block.trim_matches('\n'))
+            .filter(|block| !block.is_empty())
+            .map(|block| {
+                let lines = block.lines().map(String::from).collect::<Vec<_>>();
+                match lines.iter().position(|line| line.contains("-->")) {
+                    Some(timing) => Block {
+                        head: lines[..=timing].to_vec(),
+                        text: Some(lines[timing + 1..].join("\n")),
+                    },
+                    None => Block {
+                        head: lines,
+                        text: None,
+                    },
+                }
+            })
+            .collect();
+        Self { blocks }
+    }
+
+    /// The text of every cue, in order.
+    pub fn texts(&self) -> Vec<&str> {
+        self.blocks
+            .iter()
+            .filter_map(|block| block.text.as_deref())
+            .collect()
+    }
+
+    /// Replaces the text of the cues, in the order of `texts`. Blank lines are dropped
+    /// from the translations, since a blank line would end the cue early.
+    pub fn replace(&mut self, texts: Vec<String>) {
+        let cues = self
+            .blocks
+            .iter_mut()
+            .filter_map(|block| block.text.as_mut());
+        for (text, translated) in cues.zip(texts) {
+            *text = translated
+                .lines()
+                .map(str::trim_end)
+                .filter(|line| !line.trim().is_empty())
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut lines = block.head.clone();
+                lines.extend(block.text.iter().filter(|text| !text.is_empty()).cloned());
+                lines.join("\n")
+            })
+            .collect::<Vec<_>>();
+        format!("{}\n", blocks.join("\n\n"))
+    }
+}
@@ -0,0 +1,156 @@
+use crate::chunk::estimate_tokens;
+use crate::core::{collect, open_stream, RequestExtra, Sampling};
+use crate::language;
+use crate::retry::RetryPolicy;
+use anyhow::{Context, Result};
+use openai::{
+    chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole},
+    Credentials,
+};
+use std::env;
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation you receive in {language}. Keep the topics, what was decided, what is still open, and any names, code or numbers likely to be referred to later, as a concise bulleted list. Answer with the summary only.";
+
+/// Starts the system message that stands in for compacted messages, followed by the
+/// language in parentheses.
+const SUMMARY_HEADER: &str = "Summary of the earlier conversation";
+
+/// The language to summarize in: `setting` (`summary_language` in the config) if given,
+/// else the one of the locale (`LC_ALL`, `LC_MESSAGES`, `LANG`), else English.
+pub fn language(setting: Option<&str>) -> Result<&'static str> {
+    if let Some(setting) = setting {
+        return language::name(setting);
+    }
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    let code = locale.split(['_', '.', '@']).next().unwrap_or_default();
+    Ok(language::name(code).unwrap_or("English"))
+}
+
+/// Estimated tokens of the content of `messages`.
+pub fn tokens(messages: &[ChatCompletionMessage]) -> usize {
+    messages
+        .iter()
+        .filter_map(|m| m.content.as_deref())
+        .map(estimate_tokens)
+        .sum()
+}
+
+/// `messages` as a labelled transcript; a summary from an earlier compaction is kept as such.
+fn transcript(messages: &[ChatCompletionMessage]) -> String {
+    messages
+        .iter()
+        .filter_map(|m| {
+            let label = match m.role {
+                ChatCompletionMessageRole::User => "User",
+                ChatCompletionMessageRole::Assistant => "Assistant",
+                _ => "Earlier summary",
+            };
+            let content = m.content.as_deref()?;
+            let content = match m.role {
+                ChatCompletionMessageRole::System => content
+                    .split_once('\n')
+                    .filter(|(header, _)| header.starts_with(SUMMARY_HEADER))
+                    .map_or(content, |(_, summary)| summary),
+                _ => content,
+            };
+            Some(format!("{}: {}", label, content.trim()))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Asks `model` for a summary of `messages` in `language`, showing a spinner meanwhile.
+pub async fn generate_summary(
+    messages: &[ChatCompletionMessage],
+    language: &str,
+    model: &str,
+    credentials: &Credentials,
+    extra: &RequestExtra,
+    policy: &RetryPolicy,
+) -> Result<String> {
+    let request = vec![
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::System,
+            content: Some(SUMMARY_PROMPT.replace("{language}", language)),
+            ..Default::default()
+        },
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(transcript(messages)),
+            ..Default::default()
+        },
+    ];
+    let stream = open_stream(
+        ChatCompletionDelta::builder(model, request),
+        credentials,
+        extra,
+        &Sampling::default(),
+        policy,
+    )
+    .await
+    .with_context(|| "Can't open Stream")?;
+    collect(stream)
+        .await?
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .map(|summary| summary.trim().to_string())
+        .with_context(|| "Can't get the summary")
+}
+
+/// The system message that replaces compacted messages, e.g.
+/// `Summary of the earlier conversation (English):` and the summary below it.
+pub fn message(summary: &str, language: &str) -> ChatCompletionMessage {
+    ChatCompletionMessage {
+        role: ChatCompletionMessageRole::System,
+        content: Some(format!("{} ({}):\n{}", SUMMARY_HEADER, language, summary)),
+        ..Default::default()
+    }
+}
+
+/// The rest of the header line after `SUMMARY_HEADER` if `message` is a summary.
+fn header(message: &ChatCompletionMessage) -> Option<&str> {
+    if message.role != ChatCompletionMessageRole::System {
+        return None;
+    }
+    message
+        .content
+        .as_deref()?
+        .lines()
+        .next()?
+        .strip_prefix(SUMMARY_HEADER)
+}
+
+/// Whether `message` stands in for compacted messages.
+pub fn is_summary(message: &ChatCompletionMessage) -> bool {
+    header(message).is_some()
+}
+
+/// Index of the last summary in `messages` written in another language than `language`.
+pub fn stale(messages: &[ChatCompletionMessage], language: &str) -> Option<usize> {
+    messages
+        .iter()
+        .rposition(|m| header(m).is_some_and(|rest| rest != format!(" ({}):", language)))
+}
+
+/// Where to cut `messages` so the last `keep` exchanges stay as they are, if anything
+/// after `boundary` other than a single earlier summary comes before them.
+pub fn split(messages: &[ChatCompletionMessage], boundary: usize, keep: usize) -> Option<usize> {
+    let starts = messages
+        .iter()
+        .enumerate()
+        .skip(boundary)
+        .filter(|(_, m)| m.role == ChatCompletionMessageRole::User)
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    let split = *starts.get(starts.len().checked_sub(keep)?)?;
+    match &messages[boundary..split] {
+        [] => None,
+        [only] if is_summary(only) => None,
+        _ => Some(split),
+    }
+}
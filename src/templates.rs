@@ -0,0 +1,188 @@
+use crate::config::data_dir;
+use crate::core::{Model, ReasoningEffort, Sampling};
+use anyhow::{bail, Context, Result};
+use inquire::Text;
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{read_dir, read_to_string};
+use std::io::{stdin, IsTerminal};
+use std::path::PathBuf;
+
+/// A conversation to start from, read from `templates/<name>.yaml` in the config directory:
+///
+/// ```yaml
+/// description: Bug report from a crash log
+/// model: gpt-4o
+/// temperature: 0.2
+/// messages:
+///   - role: system
+///     content: You turn crash logs into concise bug reports.
+///   - role: user
+///     content: "Crash log:\n{{crash_log}}\n\nWrite a bug report."
+/// ```
+///
+/// `{{name}}` placeholders are filled from `--var name=value` (`@path` reads a file),
+/// or asked for when missing.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub model: Option<Model>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    top_p: Option<f32>,
+    #[serde(default)]
+    max_tokens: Option<u64>,
+    #[serde(default)]
+    reasoning_effort: Option<ReasoningEffort>,
+    pub messages: Vec<TemplateMessage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateMessage {
+    pub role: ChatCompletionMessageRole,
+    pub content: String,
+}
+
+pub fn dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("templates"))
+}
+
+pub fn load(name: &str) -> Result<Template> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        bail!("Invalid template name: {}", name)
+    }
+    let path = ["yaml", "yml"]
+        .iter()
+        .map(|extension| dir().map(|dir| dir.join(format!("{}.{}", name, extension))))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .find(|path| path.is_file())
+        .with_context(|| {
+            format!(
+                "Unknown template: {} (available: {})",
+                name,
+                list().unwrap_or_default().join(", ")
+            )
+        })?;
+    let text = read_to_string(&path).with_context(|| format!("Can't read {}", path.display()))?;
+    serde_yaml::from_str(&text).with_context(|| format!("Invalid template {}", path.display()))
+}
+
+pub fn list() -> Result<Vec<String>> {
+    let dir = dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = read_dir(dir)
+        .with_context(|| "Can't read templates directory")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "yaml" || extension == "yml")
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+impl Template {
+    pub fn sampling(&self) -> Sampling {
+        Sampling {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            reasoning_effort: self.reasoning_effort,
+            n: None,
+        }
+    }
+
+    /// Names of the `{{placeholders}}` in the messages, in order of appearance.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = Vec::<String>::new();
+        for message in &self.messages {
+            placeholders(&message.content, |_, name| {
+                if let Some(name) = name.filter(|name| !name.is_empty()) {
+                    if !names.iter().any(|known| known == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            });
+        }
+        names
+    }
+
+    /// The messages with every placeholder replaced. Variables missing from `values`
+    /// are asked for in the terminal; without one they are an error.
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<Vec<ChatCompletionMessage>> {
+        let mut values = values.clone();
+        let missing = self
+            .variables()
+            .into_iter()
+            .filter(|name| !values.contains_key(name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() && !stdin().is_terminal() {
+            bail!(
+                "Missing template variables: {} (pass them with --var name=value)",
+                missing.join(", ")
+            )
+        }
+        for name in missing {
+            let value = Text::new(&format!("{}:", name)).prompt()?;
+            values.insert(name, value);
+        }
+
+        Ok(self
+            .messages
+            .iter()
+            .map(|message| ChatCompletionMessage {
+                role: message.role,
+                content: Some(substitute(&message.content, &values)),
+                ..Default::default()
+            })
+            .collect())
+    }
+}
+
+/// Calls `f` with the text between placeholders and the trimmed name of each placeholder.
+pub(crate) fn placeholders<'a>(text: &'a str, mut f: impl FnMut(&'a str, Option<&'a str>)) {
+    let mut rest = text;
+    while let Some((before, after)) = rest.split_once("{{") {
+        let Some((name, after)) = after.split_once("}}") else {
+            break;
+        };
+        f(before, Some(name.trim()));
+        rest = after;
+    }
+    f(rest, None);
+}
+
+/// `text` with the placeholders that have a value replaced.
+pub(crate) fn substitute(text: &str, values: &HashMap<String, String>) -> String {
+    let mut substituted = String::with_capacity(text.len());
+    placeholders(text, |before, name| {
+        substituted.push_str(before);
+        if let Some(name) = name {
+            match values.get(name) {
+                Some(value) => substituted.push_str(value),
+                None => substituted.push_str(&format!("{{{{{}}}}}", name)),
+            }
+        }
+    });
+    substituted
+}
+
+/// A `--var` value: `@path` stands for the contents of the file.
+pub fn value(value: &str) -> Result<String> {
+    match value.strip_prefix('@') {
+        Some(path) => read_to_string(path).with_context(|| format!("Can't read {}", path)),
+        None => Ok(value.to_string()),
+    }
+}
@@ -0,0 +1,91 @@
+use std::env;
+use std::io::{stdout, IsTerminal, Write};
+use std::path::Path;
+
+/// OSC escape sequences for terminals that understand them (WezTerm, Kitty, iTerm2, ...).
+/// Enabled with `terminal_integration: true`; nothing is written otherwise.
+pub struct TerminalIntegration {
+    enabled: bool,
+}
+
+impl TerminalIntegration {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: enabled && stdout().is_terminal(),
+        }
+    }
+
+    pub fn set_title(&self, title: &str) {
+        self.emit(&format!("\x1b]2;{}\x07", title));
+    }
+
+    /// OSC 133 A/B: the prompt starts here and the user's input follows immediately.
+    pub fn prompt_start(&self) {
+        self.emit("\x1b]133;A\x07\x1b]133;B\x07");
+    }
+
+    /// OSC 133 C: output for the submitted input starts here.
+    pub fn output_start(&self) {
+        self.emit("\x1b]133;C\x07");
+    }
+
+    /// OSC 133 D: the output for the submitted input is finished.
+    pub fn output_end(&self) {
+        self.emit("\x1b]133;D;0\x07");
+    }
+
+    fn emit(&self, sequence: &str) {
+        if self.enabled {
+            print!("{}", sequence);
+            let _ = stdout().flush();
+        }
+    }
+}
+
+/// Whether the terminal renders OSC 8 hyperlinks, judged by what the terminals known
+/// to support them put in the environment. `FERRITE_HYPERLINKS=0` or `1` overrides it.
+pub fn hyperlinks() -> bool {
+    if let Ok(value) = env::var("FERRITE_HYPERLINKS") {
+        return value != "0";
+    }
+    let var = |name| env::var(name).unwrap_or_default();
+    matches!(
+        var("TERM_PROGRAM").as_str(),
+        "iTerm.app" | "WezTerm" | "vscode" | "ghostty" | "Hyper"
+    ) || env::var("KITTY_WINDOW_ID").is_ok()
+        || env::var("WT_SESSION").is_ok()
+        || var("VTE_VERSION")
+            .parse::<u32>()
+            .is_ok_and(|version| version >= 5000)
+        || ["kitty", "alacritty", "foot"]
+            .iter()
+            .any(|name| var("TERM").contains(name))
+}
+
+pub fn link_start(target: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\", target)
+}
+
+pub fn link_end() -> &'static str {
+    "\x1b]8;;\x1b\\"
+}
+
+/// `text` linked to `target` when stdout is a terminal with hyperlinks, plain otherwise.
+pub fn link(text: &str, target: &str) -> String {
+    if stdout().is_terminal() && hyperlinks() {
+        format!("{}{}{}", link_start(target), text, link_end())
+    } else {
+        text.to_string()
+    }
+}
+
+/// The path linked to its `file://` URL.
+pub fn file_link(path: &Path) -> String {
+    match path.canonicalize() {
+        Ok(absolute) => link(
+            &path.display().to_string(),
+            &format!("file://{}", absolute.display()),
+        ),
+        Err(_) => path.display().to_string(),
+    }
+}
@@ -0,0 +1,212 @@
+use crate::config::Config;
+use crate::core::Headers;
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use openai::Credentials;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::{Certificate, Client, Proxy, StatusCode};
+use reqwest_eventsource::{Event, EventSource, RequestBuilderExt};
+use serde_json::Value;
+use std::fmt::Debug;
+use std::fs::read;
+
+/// How requests reach the API and the web. `ReqwestTransport` is the real one; tests and
+/// library users can hand another to `RequestExtra::transport`, e.g. a mock.
+pub trait ApiTransport: Debug + Send + Sync {
+    /// Posts the JSON `body` to `url` and streams the server-sent events of the answer.
+    fn stream(
+        &self,
+        url: &str,
+        api_key: &str,
+        headers: &Headers,
+        body: &Value,
+    ) -> BoxFuture<'static, Result<Reply>>;
+
+    /// Downloads `url`, refusing bodies over `limit` bytes.
+    fn download(&self, url: &str, limit: usize) -> BoxFuture<'static, Result<Download>>;
+
+    /// Opens a connection to the API ahead of the first request. Does nothing by default.
+    fn warm_up(&self, _credentials: &Credentials) {}
+}
+
+/// The data of the server-sent events of an answer. An error ends the stream early,
+/// e.g. when the connection drops; the server closing it ends it cleanly.
+pub type Events = BoxStream<'static, Result<String>>;
+
+/// What became of a streaming request.
+pub enum Reply {
+    /// The API accepted the request.
+    Events(Events),
+    /// The API answered with an error status.
+    Refused {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: String,
+    },
+    /// The API couldn't be reached.
+    Unreachable(String),
+}
+
+/// A downloaded page.
+pub struct Download {
+    /// The lowercased `Content-Type`, empty if there was none.
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+/// Sends requests with one `reqwest::Client`, so connections are pooled and transport
+/// settings (proxies, TLS, timeouts) live in one place.
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+impl ApiTransport for ReqwestTransport {
+    fn stream(
+        &self,
+        url: &str,
+        api_key: &str,
+        headers: &Headers,
+        body: &Value,
+    ) -> BoxFuture<'static, Result<Reply>> {
+        let mut request = self.client.post(url).bearer_auth(api_key).json(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let source = request.eventsource();
+        Box::pin(async move {
+            let mut source = source?;
+            match source.next().await {
+                Some(Ok(Event::Open)) => Ok(Reply::Events(events(source))),
+                Some(Err(reqwest_eventsource::Error::InvalidStatusCode(status, response))) => {
+                    let headers = response.headers().clone();
+                    let body = response.text().await.unwrap_or_default();
+                    Ok(Reply::Refused {
+                        status,
+                        headers,
+                        body,
+                    })
+                }
+                Some(Err(reqwest_eventsource::Error::Transport(e))) => {
+                    Ok(Reply::Unreachable(e.to_string()))
+                }
+                Some(Err(e)) => Err(e).with_context(|| "Can't connect to the API"),
+                Some(Ok(Event::Message(_))) | None => bail!("Stream closed before it was opened"),
+            }
+        })
+    }
+
+    fn download(&self, url: &str, limit: usize) -> BoxFuture<'static, Result<Download>> {
+        let request = self.client.get(url);
+        let url = url.to_string();
+        Box::pin(async move {
+            let mut response = request
+                .send()
+                .await
+                .with_context(|| format!("Can't fetch {}", url))?;
+            let status = response.status();
+            if !status.is_success() {
+                bail!("Can't fetch {}: {}", url, status);
+            }
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_lowercase();
+            let too_large = || anyhow!("{} is larger than {} MB", url, limit / 1024 / 1024);
+            if response
+                .content_length()
+                .is_some_and(|length| length > limit as u64)
+            {
+                return Err(too_large());
+            }
+            // Content-Length can be missing or wrong, so the limit is checked while reading too.
+            let mut body = Vec::new();
+            while let Some(chunk) = response
+                .chunk()
+                .await
+                .with_context(|| format!("Can't read {}", url))?
+            {
+                if body.len() + chunk.len() > limit {
+                    return Err(too_large());
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Ok(Download { content_type, body })
+        })
+    }
+
+    /// A `GET models` in the background whose result is ignored, so the first question
+    /// doesn't wait for DNS, TLS and proxy setup.
+    fn warm_up(&self, credentials: &Credentials) {
+        let request = self
+            .client
+            .get(format!("{}models", credentials.base_url()))
+            .bearer_auth(credentials.api_key());
+        tokio::spawn(async move {
+            let _ = request.send().await;
+        });
+    }
+}
+
+/// The data of the events of an opened `source`. Stopping at the end of the stream keeps
+/// the `EventSource` from reconnecting; dropping the stream closes the connection.
+fn events(source: EventSource) -> Events {
+    stream::unfold(Some(source), |source| async move {
+        let mut source = source?;
+        loop {
+            match source.next().await {
+                Some(Ok(Event::Open)) => continue,
+                Some(Ok(Event::Message(message))) => return Some((Ok(message.data), Some(source))),
+                Some(Err(reqwest_eventsource::Error::StreamEnded)) | None => {
+                    source.close();
+                    return None;
+                }
+                Some(Err(e)) => {
+                    source.close();
+                    return Some((
+                        Err(anyhow::Error::new(e).context("The answer was cut off")),
+                        None,
+                    ));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Sets up the transport from the `proxy` and `ca_bundle` settings. Without `proxy`,
+/// `HTTPS_PROXY`/`HTTP_PROXY` from the environment are used. `insecure` turns off
+/// certificate verification, for lab setups with self-signed certificates.
+pub fn configure(config: &Config, insecure: bool) -> Result<ReqwestTransport> {
+    let mut builder = Client::builder();
+    if let Some(proxy) = config.get_proxy() {
+        builder =
+            builder.proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy: {}", proxy))?);
+    }
+    if let Some(path) = config.get_ca_bundle() {
+        let pem = read(path).with_context(|| format!("Can't read CA bundle {}", path))?;
+        for certificate in Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Can't parse CA bundle {}", path))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if insecure {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(ReqwestTransport::new(
+        builder
+            .build()
+            .with_context(|| "Can't set up the HTTP client")?,
+    ))
+}
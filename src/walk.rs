@@ -0,0 +1,244 @@
+use crate::chunk::estimate_tokens;
+use anyhow::{Context, Result};
+use std::fs::{read, read_dir, read_to_string};
+use std::path::Path;
+
+/// Files of a directory tree concatenated with path headers, for use as context.
+#[derive(Debug, Default)]
+pub struct DirContext {
+    pub text: String,
+    pub files: usize,
+    /// Matching files left out because the token budget ran out.
+    pub skipped: Vec<String>,
+}
+
+/// Walks `root` and concatenates the text files matching `include` (everything when
+/// empty) and not matching `exclude`, skipping what `.gitignore` files ignore.
+/// Files are added in path order until `max_tokens` is reached; the rest are only listed.
+pub fn load(
+    root: &Path,
+    include: &[String],
+    exclude: &[String],
+    max_tokens: usize,
+) -> Result<DirContext> {
+    let mut paths = Vec::new();
+    walk(root, "", &mut Vec::new(), &mut paths)
+        .with_context(|| format!("Can't read {}", root.display()))?;
+    paths.sort();
+
+    let mut context = DirContext::default();
+    let mut used = 0;
+    for relative in paths {
+        if !include.is_empty() && !include.iter().any(|p| matches_path(p, &relative)) {
+            continue;
+        }
+        if exclude.iter().any(|p| matches_path(p, &relative)) {
+            continue;
+        }
+        let Some(content) = text_file(&root.join(&relative)) else {
+            continue;
+        };
+        let section = format!("=== {} ===\n{}\n", relative, content.trim_end());
+        let tokens = estimate_tokens(&section);
+        if !context.skipped.is_empty() || used + tokens > max_tokens {
+            context.skipped.push(relative);
+            continue;
+        }
+        used += tokens;
+        context.files += 1;
+        context.text.push_str(&section);
+    }
+    if !context.skipped.is_empty() {
+        context.text.push_str(&format!(
+            "=== not included (token budget) ===\n{}\n",
+            context.skipped.join("\n")
+        ));
+    }
+    Ok(context)
+}
+
+/// `.gitignore` patterns together with the directory (relative to the root) they apply to.
+struct Ignore {
+    base: String,
+    patterns: Vec<String>,
+}
+
+fn walk(
+    root: &Path,
+    relative: &str,
+    ignores: &mut Vec<Ignore>,
+    out: &mut Vec<String>,
+) -> Result<()> {
+    let dir = root.join(relative);
+    let pushed = match read_to_string(dir.join(".gitignore")) {
+        Ok(gitignore) => {
+            ignores.push(Ignore {
+                base: relative.to_string(),
+                patterns: gitignore
+                    .lines()
+                    .map(str::trim_end)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(String::from)
+                    .collect(),
+            });
+            true
+        }
+        Err(_) => false,
+    };
+
+    let mut entries = read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name == ".git" {
+            continue;
+        }
+        let path = if relative.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative, name)
+        };
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let is_dir = file_type.is_dir();
+        if ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            walk(root, &path, ignores, out)?;
+        } else if file_type.is_file() {
+            out.push(path);
+        }
+    }
+
+    if pushed {
+        ignores.pop();
+    }
+    Ok(())
+}
+
+/// Applies gitignore rules: the last matching pattern wins and `!` re-includes.
+fn ignored(ignores: &[Ignore], path: &str, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for ignore in ignores {
+        let relative = if ignore.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(&ignore.base)
+                .and_then(|p| p.strip_prefix('/'))
+            {
+                Some(relative) => relative,
+                None => continue,
+            }
+        };
+        for pattern in &ignore.patterns {
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern.as_str()),
+            };
+            let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern),
+            };
+            if dir_only && !is_dir {
+                continue;
+            }
+            if matches_path(pattern, relative) {
+                ignored = !negated;
+            }
+        }
+    }
+    ignored
+}
+
+/// Gitignore-style matching: a pattern without `/` matches the file name at any depth,
+/// otherwise it's matched against the whole path relative to the root.
+pub fn matches_path(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+    if pattern.trim_end_matches('/').contains('/') {
+        glob(pattern.trim_start_matches('/'), path)
+    } else {
+        glob(pattern, path.rsplit('/').next().unwrap_or(path))
+    }
+}
+
+/// Matches `*` and `?` within a path segment, `**` across segments and `[...]` classes.
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    glob_from(&pattern, &text)
+}
+
+fn glob_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => match pattern[2..].strip_prefix(&['/']) {
+            // `**/` matches zero or more whole directories.
+            Some(rest) => {
+                glob_from(rest, text)
+                    || (0..text.len()).any(|i| text[i] == '/' && glob_from(rest, &text[i + 1..]))
+            }
+            None => (0..=text.len()).any(|i| glob_from(&pattern[2..], &text[i..])),
+        },
+        Some('*') => (0..=text.len())
+            .take_while(|i| *i == 0 || text[i - 1] != '/')
+            .any(|i| glob_from(&pattern[1..], &text[i..])),
+        Some('?') => !text.is_empty() && text[0] != '/' && glob_from(&pattern[1..], &text[1..]),
+        Some('[') => match class_end(pattern) {
+            Some(end) => {
+                !text.is_empty()
+                    && text[0] != '/'
+                    && in_class(&pattern[1..end], text[0])
+                    && glob_from(&pattern[end + 1..], &text[1..])
+            }
+            None => text.first() == Some(&'[') && glob_from(&pattern[1..], &text[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_from(&pattern[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && glob_from(&pattern[1..], &text[1..]),
+    }
+}
+
+fn class_end(pattern: &[char]) -> Option<usize> {
+    pattern
+        .iter()
+        .enumerate()
+        .skip(2)
+        .find(|(_, c)| **c == ']')
+        .map(|(i, _)| i)
+}
+
+fn in_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+/// Contents of a UTF-8 text file; binaries and unreadable files are skipped.
+fn text_file(path: &Path) -> Option<String> {
+    let bytes = read(path).ok()?;
+    if bytes.iter().take(8000).any(|b| *b == 0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
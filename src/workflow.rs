@@ -0,0 +1,187 @@
+use crate::core::{ask, collect, open_stream, Model, RequestExtra, Sampling};
+use crate::retry::RetryPolicy;
+use crate::spinner::Spinner;
+use crate::templates::{placeholders, substitute};
+use anyhow::{bail, Context, Result};
+use openai::{
+    chat::{ChatCompletionDelta, ChatCompletionMessage, ChatCompletionMessageRole},
+    Credentials,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// Steps run one after another, each prompt filled with the outputs before it:
+///
+/// ```yaml
+/// model: gpt-4o
+/// steps:
+///   - name: outline
+///     prompt: "Outline an article about {{input}}"
+///   - name: draft
+///     model: gpt-4o-mini
+///     prompt: "Write the article from this outline:\n{{previous}}"
+///   - system: You are a careful copy editor.
+///     prompt: "Proofread this draft:\n{{draft}}"
+/// ```
+///
+/// `{{input}}` is the prompt given to fask, `{{previous}}` the output of the step before,
+/// `{{<name>}}` the output of a named step, and `--var` values fill the rest.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workflow {
+    /// Model of steps that don't name one.
+    #[serde(default)]
+    pub model: Option<Model>,
+    /// System prompt of steps that don't have one.
+    #[serde(default)]
+    pub system: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub model: Option<Model>,
+    #[serde(default)]
+    pub system: Option<String>,
+    pub prompt: String,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+}
+
+impl Workflow {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+        let workflow: Workflow = serde_yaml::from_str(&text)
+            .with_context(|| format!("Invalid workflow {}", path.display()))?;
+        if workflow.steps.is_empty() {
+            bail!("{} has no steps", path.display())
+        }
+        let mut names = Vec::new();
+        for name in workflow.steps.iter().filter_map(|step| step.name.as_ref()) {
+            if name == "input" || name == "previous" || names.contains(&name) {
+                bail!("Step name `{}` is reserved or used twice", name)
+            }
+            names.push(name);
+        }
+        Ok(workflow)
+    }
+
+    /// Runs the steps; progress goes to stderr and the answer of the last step is passed
+    /// to `on_delta` as it arrives (see `core::ask`). `sampling` gives the sampling of a
+    /// model. Returns the output of the last step.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn run(
+        &self,
+        mut values: HashMap<String, String>,
+        default_model: Model,
+        credentials: &Credentials,
+        extra: &RequestExtra,
+        sampling: impl Fn(&str) -> Sampling,
+        policy: &RetryPolicy,
+        mut on_delta: impl FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
+        // Every placeholder has to be known before the first request is paid for.
+        let mut known = values.keys().cloned().collect::<Vec<_>>();
+        for (i, step) in self.steps.iter().enumerate() {
+            let texts = [
+                Some(&step.prompt),
+                step.system.as_ref().or(self.system.as_ref()),
+            ];
+            for text in texts.into_iter().flatten() {
+                let mut unknown = None;
+                placeholders(text, |_, name| {
+                    if let Some(name) = name {
+                        if !known.iter().any(|known| known == name)
+                            && (name != "previous" || i == 0)
+                        {
+                            unknown.get_or_insert(name.to_string());
+                        }
+                    }
+                });
+                if let Some(name) = unknown {
+                    bail!(
+                        "Step {} uses {{{{{}}}}}, which has no value at that point",
+                        i + 1,
+                        name
+                    )
+                }
+            }
+            known.extend(step.name.clone());
+        }
+
+        let mut output = String::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let model = step
+                .model
+                .clone()
+                .or(self.model.clone())
+                .unwrap_or(default_model.clone());
+            let model = model.as_str();
+            eprintln!(
+                "[{}/{}] {} ({})",
+                i + 1,
+                self.steps.len(),
+                step.name.as_deref().unwrap_or("step"),
+                model
+            );
+
+            let mut messages = Vec::new();
+            if let Some(system) = step.system.as_ref().or(self.system.as_ref()) {
+                messages.push(ChatCompletionMessage {
+                    role: ChatCompletionMessageRole::System,
+                    content: Some(substitute(system, &values)),
+                    ..Default::default()
+                });
+            }
+            messages.push(ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(substitute(&step.prompt, &values)),
+                ..Default::default()
+            });
+
+            let sampling = Sampling {
+                temperature: step.temperature,
+                ..Default::default()
+            }
+            .or(sampling(model));
+            let stream = open_stream(
+                ChatCompletionDelta::builder(model, messages),
+                credentials,
+                extra,
+                &sampling,
+                policy,
+            )
+            .await
+            .with_context(|| format!("Step {} failed", i + 1))?;
+            let completion = if i + 1 == self.steps.len() {
+                let mut spinner = Spinner::start();
+                ask(stream, |text| {
+                    spinner.stop();
+                    on_delta(text)
+                })
+                .await?
+            } else {
+                collect(stream).await?
+            };
+            output = completion
+                .choices
+                .first()
+                .and_then(|choice| choice.message.content.clone())
+                .with_context(|| format!("Step {} returned no answer", i + 1))?;
+
+            values.insert(String::from("previous"), output.clone());
+            if let Some(name) = &step.name {
+                values.insert(name.clone(), output.clone());
+            }
+        }
+        Ok(output)
+    }
+}